@@ -0,0 +1,477 @@
+//! Who may log in, when and from where, enforced by `pam_sm_acct_mgmt`.
+//!
+//! Rules are given in the `[access]` section:
+//!
+//! ```text
+//! [access]
+//! hours = alice: Mo-Fr 08:00-18:00
+//! hours = @ops: *
+//! hours = *: Mo-Fr 07:00-20:00, Sa 09:00-13:00
+//! allow_rhost = 10.0.0.0/8, *.example.org
+//! max_sessions = 4
+//! maintenance_file = /etc/pam_rc2022.maintenance
+//! admins = @wheel
+//! ```
+//!
+//! A user gets the `hours` given for them by name if there are any, otherwise
+//! those of their groups, otherwise those for `*`. Without any they may log
+//! in at any time. Windows are in local time and may run past midnight, like
+//! `Fr 22:00-06:00`.
+
+use crate::{
+    args::ModuleArgs,
+    config::Config,
+    diagnose,
+    event::now,
+    filter::HostPattern,
+    log::{Logger, Message},
+    passwd, session, Pam, PamResult, PamResultCode,
+};
+use std::{fmt, path::PathBuf};
+
+/// The `[access]` section.
+#[derive(Debug, Default)]
+pub struct Access {
+    hours: Vec<(Who, Vec<Window>)>,
+    rhosts: Vec<HostPattern>,
+    /// `max_sessions`: how many sessions a user may have open at once.
+    pub max_sessions: Option<usize>,
+    /// `maintenance_file`: while this file exists, only admins may log in.
+    pub maintenance_file: Option<PathBuf>,
+    admins: Vec<Who>,
+}
+
+/// Who an `hours` line or `admins` entry is about.
+#[derive(Debug, PartialEq, Eq)]
+enum Who {
+    User(String),
+    /// `@group`.
+    Group(String),
+    /// `*`.
+    Anyone,
+}
+
+impl Who {
+    fn parse(value: &str) -> Result<Who, String> {
+        Ok(match value {
+            "" | "@" => return Err(format!("invalid user or group {:?}", value)),
+            "*" => Who::Anyone,
+            _ => match value.strip_prefix('@') {
+                Some(group) => Who::Group(group.to_string()),
+                None => Who::User(value.to_string()),
+            },
+        })
+    }
+
+    fn matches(&self, user: &str) -> bool {
+        match self {
+            Who::User(name) => name == user,
+            Who::Group(group) => passwd::user_in_group(user, group),
+            Who::Anyone => true,
+        }
+    }
+}
+
+/// When logins are allowed, like `Mo-Fr 08:00-18:00`.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    /// Bit 0 is Sunday, as in `tm_wday`.
+    days: u8,
+    /// Minutes since midnight.
+    start: u16,
+    end: u16,
+}
+
+const DAYS: [&str; 7] = ["Su", "Mo", "Tu", "We", "Th", "Fr", "Sa"];
+
+impl Window {
+    /// Parses `[DAYS] [HH:MM-HH:MM]`, where days are `*`, a day like `Mo` or a
+    /// range like `Mo-Fr`. Either part defaults to all of it.
+    fn parse(value: &str) -> Result<Window, String> {
+        let invalid = || format!("invalid time window {:?}", value);
+        let mut window = Window {
+            days: 0x7f,
+            start: 0,
+            end: 24 * 60,
+        };
+
+        let mut parts = value.split_whitespace();
+        let mut part = parts.next().ok_or_else(invalid)?;
+        if !part.contains(':') {
+            window.days = parse_days(part).ok_or_else(invalid)?;
+            part = match parts.next() {
+                Some(part) => part,
+                None => return Ok(window),
+            };
+        }
+        let (start, end) = part.split_once('-').ok_or_else(invalid)?;
+        window.start = parse_time(start).ok_or_else(invalid)?;
+        window.end = parse_time(end).ok_or_else(invalid)?;
+        if parts.next().is_some() || window.start == window.end {
+            return Err(invalid());
+        }
+
+        Ok(window)
+    }
+
+    /// Returns true if `minute` past midnight on `day` is in the window.
+    fn contains(&self, day: u32, minute: u16) -> bool {
+        let on = |day: u32| self.days & (1 << (day % 7)) != 0;
+        if self.start < self.end {
+            on(day) && (self.start..self.end).contains(&minute)
+        } else {
+            // Past midnight, into the next day.
+            on(day) && minute >= self.start || on(day + 6) && minute < self.end
+        }
+    }
+}
+
+fn parse_days(value: &str) -> Option<u8> {
+    if value == "*" {
+        return Some(0x7f);
+    }
+    let day = |name: &str| DAYS.iter().position(|&day| day.eq_ignore_ascii_case(name));
+    let (first, last) = match value.split_once('-') {
+        Some((first, last)) => (day(first)?, day(last)?),
+        None => (day(value)?, day(value)?),
+    };
+
+    let mut days = 0;
+    let mut d = first;
+    loop {
+        days |= 1 << d;
+        if d == last {
+            return Some(days);
+        }
+        d = (d + 1) % 7;
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight. `24:00` is the end of the day.
+fn parse_time(value: &str) -> Option<u16> {
+    let (hours, minutes) = value.split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 24 || minutes >= 60 {
+        return None;
+    }
+    let time = hours * 60 + minutes;
+    (time <= 24 * 60).then_some(time)
+}
+
+/// Why a login was refused.
+#[derive(Debug)]
+pub enum Denial {
+    /// The maintenance file exists. Holds what it says, or a default message.
+    Maintenance(String),
+    /// It's outside the user's `hours`.
+    Hours,
+    /// The remote host isn't in `allow_rhost`.
+    Rhost,
+    /// The user already has this many sessions open.
+    Sessions(usize),
+}
+
+impl Denial {
+    /// A short name for logs.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Denial::Maintenance(_) => "maintenance",
+            Denial::Hours => "hours",
+            Denial::Rhost => "rhost",
+            Denial::Sessions(_) => "max_sessions",
+        }
+    }
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denial::Maintenance(message) => f.write_str(message),
+            Denial::Hours => f.write_str("logins aren't allowed at this time"),
+            Denial::Rhost => f.write_str("logins aren't allowed from this host"),
+            Denial::Sessions(count) => write!(f, "{} sessions are open already", count),
+        }
+    }
+}
+
+impl Access {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "hours" => {
+                let (who, windows) = value
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected WHO: WINDOWS, got {:?}", value))?;
+                let windows = windows
+                    .split(',')
+                    .map(|window| Window::parse(window.trim()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.hours.push((Who::parse(who.trim())?, windows));
+            }
+            "allow_rhost" => {
+                for pattern in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                    self.rhosts.push(HostPattern::parse(pattern)?);
+                }
+            }
+            "max_sessions" => {
+                self.max_sessions = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("invalid max_sessions {:?}", value))?,
+                )
+            }
+            "maintenance_file" => self.maintenance_file = Some(PathBuf::from(value)),
+            "admins" => {
+                for who in value.split(',').map(str::trim).filter(|w| !w.is_empty()) {
+                    self.admins.push(Who::parse(who)?);
+                }
+            }
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if there are no rules to enforce.
+    pub fn is_empty(&self) -> bool {
+        self.hours.is_empty()
+            && self.rhosts.is_empty()
+            && self.max_sessions.is_none()
+            && self.maintenance_file.is_none()
+    }
+
+    /// Checks the rules that don't need to count sessions for `user` logging
+    /// in from `rhost`, which is empty for local logins, at `time`.
+    pub fn check(&self, user: &str, rhost: &str, time: u64) -> Result<(), Denial> {
+        if let Some(message) = self.maintenance() {
+            if !self.is_admin(user) {
+                return Err(Denial::Maintenance(message));
+            }
+        }
+        if !rhost.is_empty()
+            && !self.rhosts.is_empty()
+            && !self.rhosts.iter().any(|pattern| pattern.matches(rhost))
+        {
+            return Err(Denial::Rhost);
+        }
+        if !self.in_hours(user, time) {
+            return Err(Denial::Hours);
+        }
+        Ok(())
+    }
+
+    /// What the maintenance file says, if it exists.
+    fn maintenance(&self) -> Option<String> {
+        let path = self.maintenance_file.as_ref()?;
+        let message = match std::fs::read_to_string(path) {
+            Ok(message) => message.trim().to_string(),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => return None,
+            // It's there, just unreadable.
+            Err(_) => String::new(),
+        };
+        if message.is_empty() {
+            return Some("The system is down for maintenance.".to_string());
+        }
+        Some(message)
+    }
+
+    /// Root is always an admin.
+    fn is_admin(&self, user: &str) -> bool {
+        user == "root" || self.admins.iter().any(|who| who.matches(user))
+    }
+
+    fn in_hours(&self, user: &str, time: u64) -> bool {
+        let is_user = |who: &Who| matches!(who, Who::User(_));
+        let is_group = |who: &Who| matches!(who, Who::Group(_));
+        let is_anyone = |who: &Who| *who == Who::Anyone;
+
+        // Group lines are only looked at if there are no user lines for the
+        // user, since looking groups up may mean asking a directory server.
+        for level in [&is_user as &dyn Fn(&Who) -> bool, &is_group, &is_anyone] {
+            let mut windows = self
+                .hours
+                .iter()
+                .filter(|(who, _)| level(who) && who.matches(user))
+                .flat_map(|(_, windows)| windows)
+                .peekable();
+            if windows.peek().is_none() {
+                continue;
+            }
+            let (day, minute) = local_day_and_minute(time);
+            return windows.any(|window| window.contains(day, minute));
+        }
+
+        true
+    }
+}
+
+/// The day of the week, with Sunday as 0, and minute of the day of `time` in
+/// local time.
+fn local_day_and_minute(time: u64) -> (u32, u16) {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return (0, 0);
+    }
+    (tm.tm_wday as u32, (tm.tm_hour * 60 + tm.tm_min) as u16)
+}
+
+/// Enforces the `[access]` section for the user logging in.
+///
+/// Fails with `PAM_PERM_DENIED` if the login isn't allowed, and with
+/// `PAM_IGNORE` if there are no rules.
+pub fn check_login(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let access = &config.access;
+    if access.is_empty() {
+        return Err(PamResultCode::PAM_IGNORE);
+    }
+    let logger = Logger::new(pamh, args.debug);
+    let user = pamh.user().ok_or(PamResultCode::PAM_USER_UNKNOWN)?;
+    let rhost = pamh.rhost().unwrap_or_default();
+    let now = now();
+
+    let result = access.check(&user, &rhost, now).and_then(|()| {
+        let max = match access.max_sessions {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        match session::count_open(&config.state_dir, &user) {
+            Ok(open) if open >= max => Err(Denial::Sessions(open)),
+            Ok(_) => Ok(()),
+            Err(why) => {
+                // Counting is best effort, like the rest of the state.
+                diagnose(
+                    pamh,
+                    args,
+                    config.diagnostics,
+                    format!("can't count sessions: {}", why),
+                );
+                Ok(())
+            }
+        }
+    });
+
+    let denial = match result {
+        Ok(()) => return Ok(()),
+        Err(denial) => denial,
+    };
+    logger.warning(
+        Message::new("denied login")
+            .field("user", &user)
+            .field("rhost", &rhost)
+            .field("reason", denial.reason())
+            .field("detail", &denial),
+    );
+    if let Denial::Maintenance(message) = &denial {
+        if !args.silent {
+            let _ = pamh.error_msg(message.clone());
+        }
+    }
+    Err(PamResultCode::PAM_PERM_DENIED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SU: u32 = 0;
+    const MO: u32 = 1;
+    const TU: u32 = 2;
+    const TH: u32 = 4;
+    const FR: u32 = 5;
+    const SA: u32 = 6;
+
+    fn at(hours: u16, minutes: u16) -> u16 {
+        hours * 60 + minutes
+    }
+
+    #[test]
+    fn parses_days_and_times() {
+        let window = Window::parse("Mo-Fr 08:00-18:00").unwrap();
+        assert_eq!(window.days, 0b0111110);
+        assert_eq!((window.start, window.end), (at(8, 0), at(18, 0)));
+
+        let window = Window::parse("Sa").unwrap();
+        assert_eq!(window.days, 1 << SA);
+        assert_eq!((window.start, window.end), (0, at(24, 0)));
+
+        let window = Window::parse("09:00-24:00").unwrap();
+        assert_eq!(window.days, 0x7f);
+        assert_eq!((window.start, window.end), (at(9, 0), at(24, 0)));
+
+        assert_eq!(Window::parse("*").unwrap().days, 0x7f);
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        for value in [
+            "",
+            "Xy",
+            "Mo-Xy",
+            "Mo 08:00",
+            "Mo 08:00-08:00",
+            "Mo 08:60-09:00",
+            "Mo 08:00-24:01",
+            "Mo 25:00-26:00",
+            "1093:00-1100:00",
+            "Mo 08:00-09:00 extra",
+        ] {
+            assert!(Window::parse(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn huge_hours_are_rejected_by_config() {
+        let mut access = Access::default();
+        assert!(access.set("hours", "*: 1093:00-1100:00").is_err());
+        assert!(access.hours.is_empty());
+    }
+
+    #[test]
+    fn contains_within_a_day() {
+        let window = Window::parse("Mo-Fr 08:00-18:00").unwrap();
+        assert!(window.contains(MO, at(8, 0)));
+        assert!(window.contains(FR, at(17, 59)));
+        assert!(!window.contains(FR, at(18, 0)));
+        assert!(!window.contains(MO, at(7, 59)));
+        assert!(!window.contains(SA, at(12, 0)));
+        assert!(!window.contains(SU, at(12, 0)));
+    }
+
+    #[test]
+    fn contains_past_midnight() {
+        let window = Window::parse("Fr 22:00-06:00").unwrap();
+        assert!(window.contains(FR, at(22, 0)));
+        assert!(window.contains(FR, at(23, 59)));
+        assert!(window.contains(SA, at(0, 0)));
+        assert!(window.contains(SA, at(5, 59)));
+        assert!(!window.contains(SA, at(6, 0)));
+        assert!(!window.contains(SA, at(22, 0)));
+        assert!(!window.contains(FR, at(5, 0)));
+        assert!(!window.contains(TH, at(23, 0)));
+    }
+
+    #[test]
+    fn saturday_night_runs_into_sunday() {
+        let window = Window::parse("Sa 23:00-01:00").unwrap();
+        assert!(window.contains(SA, at(23, 30)));
+        assert!(window.contains(SU, at(0, 30)));
+        assert!(!window.contains(SU, at(23, 30)));
+    }
+
+    #[test]
+    fn day_ranges_wrap_around_the_week() {
+        let window = Window::parse("Fr-Mo").unwrap();
+        assert_eq!(window.days, 1 << FR | 1 << SA | 1 << SU | 1 << MO);
+        for day in [FR, SA, SU, MO] {
+            assert!(window.contains(day, at(12, 0)), "day {}", day);
+        }
+        assert!(!window.contains(TH, at(12, 0)));
+
+        let window = Window::parse("Fr-Mo 22:00-02:00").unwrap();
+        assert!(window.contains(SU, at(23, 0)));
+        assert!(window.contains(TU, at(1, 0)));
+        assert!(!window.contains(TU, at(23, 0)));
+    }
+}
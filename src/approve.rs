@@ -0,0 +1,384 @@
+//! Out-of-band approval of logins, asked for with the `approve` module
+//! argument.
+//!
+//! The login is sent to notifiers as an `approval_request` event, then the
+//! `url` of the `[approve]` section, with `{id}` replaced by the event ID, is
+//! polled until someone decides:
+//!
+//! ```text
+//! GET https://relay.example.org/approvals/5f0c9a1e7d3b48c2a6e4f1b9d8c7e2a3
+//!
+//! 200 {"decision":"allow"}     let the login through
+//! 200 {"decision":"deny"}      fail it
+//! 200 {"decision":"pending"}   ask again after poll_interval
+//! 202 or 204                   the same as pending
+//! ```
+//!
+//! Servers may hold polls open until there is a decision, up to the
+//! `timeout` of the section.
+//!
+//! Unlike other events, approval requests get 128 random bits as their ID,
+//! since anyone who can guess the URL can decide.
+
+use crate::{
+    args::ModuleArgs,
+    audit::{Deliveries, Delivery, Outcome},
+    config::{ApproveConfig, Config, HttpConfig},
+    crypto::random_hex,
+    diagnose,
+    event::{Event, EventKind},
+    http::{self, encode_path_segment, HttpError},
+    log::{Level, Log, Logger, Message},
+    notifier, Pam, PamResult, PamResultCode,
+};
+use serde::Deserialize;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// What was decided about a login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    Deny,
+    Pending,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    decision: Decision,
+}
+
+/// Why no decision was had.
+#[derive(Debug)]
+pub enum WaitError {
+    /// `url` isn't set.
+    NoUrl,
+    /// Nobody decided in time. Holds the error of the last poll, if it
+    /// failed.
+    Timeout(Option<HttpError>),
+    /// The server answered with something that isn't a decision.
+    Invalid(String),
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::NoUrl => write!(f, "no url in [approve]"),
+            WaitError::Timeout(None) => write!(f, "nobody decided in time"),
+            WaitError::Timeout(Some(why)) => {
+                write!(f, "nobody decided in time, last poll: {}", why)
+            }
+            WaitError::Invalid(why) => write!(f, "invalid response: {}", why),
+        }
+    }
+}
+
+/// Returns true if the login gets through after waiting for a decision on it
+/// ended with `result`: if it was allowed, or if there was no decision and
+/// `fail_open` is set.
+pub fn lets_through<E>(config: &ApproveConfig, result: &Result<Decision, E>) -> bool {
+    match result {
+        Ok(decision) => *decision == Decision::Allow,
+        Err(_) => config.fail_open,
+    }
+}
+
+/// Polls for a decision on the approval request `id` until there is one or
+/// `timeout` is up. Returns [Decision::Allow] or [Decision::Deny].
+///
+/// Failed polls are retried, since the server may only be restarting.
+pub fn wait(
+    config: &ApproveConfig,
+    http_config: &HttpConfig,
+    id: &str,
+    log: &mut Log,
+) -> Result<Decision, WaitError> {
+    let url = config
+        .url
+        .as_ref()
+        .ok_or(WaitError::NoUrl)?
+        .replace("{id}", &encode_path_segment(id));
+    let deadline = Instant::now() + config.timeout;
+    let mut last_error = None;
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        // curl counts whole milliseconds and takes 0 as no timeout at all.
+        if left < Duration::from_millis(1) {
+            return Err(WaitError::Timeout(last_error));
+        }
+
+        match http::get(http_config, &url, &config.headers, left) {
+            Ok((200, body)) => match serde_json::from_slice::<Response>(&body) {
+                Ok(Response {
+                    decision: Decision::Pending,
+                }) => last_error = None,
+                Ok(Response { decision }) => return Ok(decision),
+                Err(why) => return Err(WaitError::Invalid(why.to_string())),
+            },
+            Ok(_) => last_error = None,
+            Err(why) => {
+                log(
+                    Level::Debug,
+                    Message::new("can't poll for approval")
+                        .field("event", id)
+                        .field("error", &why)
+                        .into(),
+                );
+                last_error = Some(why);
+            }
+        }
+
+        let left = deadline.saturating_duration_since(Instant::now());
+        std::thread::sleep(
+            config
+                .poll_interval
+                .min(left)
+                .max(Duration::from_millis(100)),
+        );
+    }
+}
+
+/// Sends an approval request for the login to notifiers and waits for
+/// someone to decide on it.
+///
+/// Fails with `PAM_AUTH_ERR` if the login is denied, or if no decision can be
+/// had and `fail_open` isn't set.
+pub fn authenticate(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let logger = Logger::new(pamh, args.debug);
+    let mut event = Event::new(pamh, EventKind::ApprovalRequest, None);
+    event.id = match random_hex(16) {
+        Ok(id) => id,
+        Err(why) => {
+            logger.error(Message::new("can't make up an approval ID").field("error", why));
+            return Err(PamResultCode::PAM_SYSTEM_ERR);
+        }
+    };
+    let notifiers = match &config.approve.notifiers {
+        Some(names) => notifier::named(config, names),
+        None => crate::notifiers(args, config),
+    };
+
+    // Approval requests are sent straight away and never spooled, since
+    // nobody can act on one after the login has given up waiting.
+    let mut deliveries = Deliveries::new();
+    for notifier in &notifiers {
+        let delivery = match notifier.notify(&config.http, &event) {
+            Ok(()) => Delivery::Sent,
+            Err(why) => {
+                logger.warning(
+                    Message::new("can't send approval request")
+                        .field("event", &event.id)
+                        .field("notifier", notifier.name())
+                        .field("error", why),
+                );
+                Delivery::Failed
+            }
+        };
+        deliveries.insert(notifier.name().to_string(), delivery);
+    }
+    crate::audit(
+        &logger,
+        config,
+        &event,
+        Outcome::of(&deliveries),
+        &deliveries,
+    );
+
+    let decision = if deliveries.values().any(|&d| d == Delivery::Sent) {
+        if !args.silent {
+            let _ = pamh.info(config.approve.message.clone());
+        }
+        let mut log = |level, msg| logger.log(level, msg);
+        wait(&config.approve, &config.http, &event.id, &mut log).map_err(|why| why.to_string())
+    } else {
+        Err("no notifier got the approval request".to_string())
+    };
+
+    let message = |text| {
+        Message::new(text)
+            .field("event", &event.id)
+            .field("user", &event.user)
+    };
+    match &decision {
+        Ok(Decision::Allow) => logger.info(message("login approved")),
+        Ok(_) => logger.warning(message("login denied")),
+        Err(why) => {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't get approval for {}: {}", event.user, why),
+            );
+            if config.approve.fail_open {
+                logger.warning(message("letting unapproved login through"));
+            }
+        }
+    }
+    if lets_through(&config.approve, &decision) {
+        Ok(())
+    } else {
+        Err(PamResultCode::PAM_AUTH_ERR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Starts a stand-in for the approval server that answers polls with
+    /// `responses` in turn, then keeps repeating the last one. Returns the URL
+    /// to poll.
+    fn serve(responses: &[(u32, &str)]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/approvals/{{id}}", listener.local_addr().unwrap());
+        let responses: Vec<(u32, String)> = responses
+            .iter()
+            .map(|&(status, body)| (status, body.to_string()))
+            .collect();
+
+        thread::spawn(move || {
+            for (n, stream) in listener.incoming().enumerate() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => request.extend_from_slice(&buf[..len]),
+                    }
+                }
+                let (status, body) = &responses[n.min(responses.len() - 1)];
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        url
+    }
+
+    fn config(url: Option<String>, timeout_ms: u64) -> ApproveConfig {
+        let mut config = ApproveConfig::default();
+        config.url = url;
+        config.timeout = Duration::from_millis(timeout_ms);
+        config.poll_interval = Duration::from_millis(100);
+        config
+    }
+
+    fn wait_for(config: &ApproveConfig) -> Result<Decision, WaitError> {
+        let mut log = |_, _| {};
+        wait(
+            config,
+            &HttpConfig::default(),
+            "5f0c9a1e7d3b48c2a6e4f1b9d8c7e2a3",
+            &mut log,
+        )
+    }
+
+    #[test]
+    fn allow() {
+        let config = config(Some(serve(&[(200, r#"{"decision":"allow"}"#)])), 5000);
+        let result = wait_for(&config);
+        assert!(matches!(result, Ok(Decision::Allow)));
+        assert!(lets_through(&config, &result));
+    }
+
+    #[test]
+    fn deny() {
+        let config = config(Some(serve(&[(200, r#"{"decision":"deny"}"#)])), 5000);
+        let result = wait_for(&config);
+        assert!(matches!(result, Ok(Decision::Deny)));
+        assert!(!lets_through(&config, &result));
+    }
+
+    #[test]
+    fn pending_then_allow() {
+        let url = serve(&[
+            (200, r#"{"decision":"pending"}"#),
+            (202, ""),
+            (204, ""),
+            (200, r#"{"decision":"allow"}"#),
+        ]);
+        let result = wait_for(&config(Some(url), 5000));
+        assert!(matches!(result, Ok(Decision::Allow)));
+    }
+
+    #[test]
+    fn accepted_and_no_content_are_pending() {
+        for status in [202, 204] {
+            let result = wait_for(&config(Some(serve(&[(status, "")])), 500));
+            assert!(
+                matches!(result, Err(WaitError::Timeout(None))),
+                "status {}",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_body() {
+        let result = wait_for(&config(Some(serve(&[(200, "<html>")])), 5000));
+        assert!(matches!(result, Err(WaitError::Invalid(_))));
+
+        let result = wait_for(&config(
+            Some(serve(&[(200, r#"{"decision":"maybe"}"#)])),
+            5000,
+        ));
+        assert!(matches!(result, Err(WaitError::Invalid(_))));
+    }
+
+    #[test]
+    fn no_url() {
+        assert!(matches!(
+            wait_for(&config(None, 500)),
+            Err(WaitError::NoUrl)
+        ));
+    }
+
+    #[test]
+    fn timeout_fails_closed() {
+        let config = config(Some(serve(&[(200, r#"{"decision":"pending"}"#)])), 500);
+        let result = wait_for(&config);
+        assert!(matches!(result, Err(WaitError::Timeout(None))));
+        assert!(!lets_through(&config, &result));
+    }
+
+    #[test]
+    fn timeout_with_fail_open() {
+        let mut config = config(Some(serve(&[(500, "")])), 500);
+        config.fail_open = true;
+        let result = wait_for(&config);
+        assert!(matches!(result, Err(WaitError::Timeout(Some(_)))));
+        assert!(lets_through(&config, &result));
+    }
+
+    #[test]
+    fn less_than_a_millisecond_left_is_a_timeout() {
+        // A server that accepts connections but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/approvals/{{id}}", listener.local_addr().unwrap());
+        let mut config = config(Some(url), 0);
+        config.timeout = Duration::from_micros(500);
+
+        let start = Instant::now();
+        assert!(matches!(wait_for(&config), Err(WaitError::Timeout(None))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(listener);
+    }
+}
@@ -0,0 +1,172 @@
+//! Parsing of the module arguments given after the module name in /etc/pam.d.
+//!
+//! ```text
+//! session optional pam_rc2022.so debug only_remote config=/etc/security/pam_rc2022-sshd.conf
+//! ```
+
+use crate::{Pam, PamFlags, PAM_SILENT};
+
+/// The arguments a `pam_sm_*` callback was called with.
+#[derive(Debug, Default)]
+pub struct ModuleArgs {
+    /// `debug`: log extra diagnostics.
+    pub debug: bool,
+    /// `silent`: never print anything to the user. Also set if the
+    /// application passed `PAM_SILENT`.
+    pub silent: bool,
+    /// `config=PATH`: read the configuration from `PATH` instead of the default.
+    pub config: Option<String>,
+    /// `webhook=URL`: send messages to `URL` instead of the configured webhooks.
+    pub webhook: Option<String>,
+    /// `only_remote`: only send messages for logins that have a remote host.
+    pub only_remote: bool,
+    /// `auth_fail`: report a failed authentication from `pam_sm_authenticate`
+    /// and return `PAM_AUTH_ERR`. The module has to be stacked so that it is
+    /// only reached when the real authentication module failed.
+    pub auth_fail: bool,
+    /// `totp`: ask for a TOTP code in `pam_sm_authenticate`. Together with
+    /// `auth_fail`, wrong codes are reported as failed authentications.
+    pub totp: bool,
+    /// `approve`: have someone approve the login out of band in
+    /// `pam_sm_authenticate`, after the TOTP code if `totp` is given too.
+    pub approve: bool,
+    /// `lockout`: fail `pam_sm_authenticate` straight away for users and
+    /// remote hosts that the `[lockout]` section has locked.
+    pub lockout: bool,
+}
+
+impl ModuleArgs {
+    /// Parses the `flags` and arguments PAM passes to every `pam_sm_*`
+    /// callback.
+    ///
+    /// Arguments that aren't understood are logged to syslog and otherwise
+    /// ignored, so a typo in /etc/pam.d doesn't lock anyone out.
+    pub fn new(pamh: Pam<'_>, flags: PamFlags, raw: &[String]) -> ModuleArgs {
+        let mut args = ModuleArgs {
+            silent: flags & PAM_SILENT != 0,
+            ..ModuleArgs::default()
+        };
+
+        for arg in raw {
+            if let Err(why) = args.set(arg) {
+                let _ = pamh.syslog(libc::LOG_WARNING, format!("{}, ignoring it", why));
+            }
+        }
+
+        args
+    }
+
+    fn set(&mut self, arg: &str) -> Result<(), String> {
+        match arg.split_once('=') {
+            None => match arg {
+                "debug" => self.debug = true,
+                "silent" => self.silent = true,
+                "only_remote" => self.only_remote = true,
+                "auth_fail" => self.auth_fail = true,
+                "totp" => self.totp = true,
+                "approve" => self.approve = true,
+                "lockout" => self.lockout = true,
+                "config" | "webhook" => return Err(format!("argument {:?} needs a value", arg)),
+                _ => return Err(format!("unknown argument {:?}", arg)),
+            },
+            Some((key, value)) => match key {
+                "config" => self.config = Some(value.to_string()),
+                "webhook" => self.webhook = Some(value.to_string()),
+                "debug" | "silent" | "only_remote" | "auth_fail" | "totp" | "approve"
+                | "lockout" => return Err(format!("argument {:?} doesn't take a value", key)),
+                _ => return Err(format!("unknown argument {:?}", key)),
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[&str]) -> Result<ModuleArgs, String> {
+        let mut args = ModuleArgs::default();
+        for arg in raw {
+            args.set(arg)?;
+        }
+        Ok(args)
+    }
+
+    #[test]
+    fn parses_flags_and_values() {
+        let args = parse(&[
+            "debug",
+            "silent",
+            "only_remote",
+            "auth_fail",
+            "totp",
+            "approve",
+            "lockout",
+            "config=/etc/security/pam_rc2022-sshd.conf",
+            "webhook=https://discord.example/a?b=c",
+        ])
+        .unwrap();
+        assert!(args.debug && args.silent && args.only_remote && args.auth_fail);
+        assert!(args.totp && args.approve && args.lockout);
+        assert_eq!(
+            args.config.as_deref(),
+            Some("/etc/security/pam_rc2022-sshd.conf")
+        );
+        // Only the first `=` separates the value.
+        assert_eq!(
+            args.webhook.as_deref(),
+            Some("https://discord.example/a?b=c")
+        );
+    }
+
+    #[test]
+    fn defaults_to_nothing() {
+        let args = parse(&[]).unwrap();
+        assert!(!args.debug && !args.silent && !args.only_remote && !args.auth_fail);
+        assert!(!args.totp && !args.approve && !args.lockout);
+        assert_eq!(args.config, None);
+        assert_eq!(args.webhook, None);
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        assert_eq!(
+            parse(&["config"]).unwrap_err(),
+            "argument \"config\" needs a value"
+        );
+        assert_eq!(
+            parse(&["webhook"]).unwrap_err(),
+            "argument \"webhook\" needs a value"
+        );
+    }
+
+    #[test]
+    fn rejects_values_for_flags() {
+        assert_eq!(
+            parse(&["debug=1"]).unwrap_err(),
+            "argument \"debug\" doesn't take a value"
+        );
+        assert_eq!(
+            parse(&["lockout="]).unwrap_err(),
+            "argument \"lockout\" doesn't take a value"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_arguments() {
+        assert_eq!(parse(&["Debug"]).unwrap_err(), "unknown argument \"Debug\"");
+        assert_eq!(
+            parse(&["conf=/etc/x"]).unwrap_err(),
+            "unknown argument \"conf\""
+        );
+        assert_eq!(parse(&[""]).unwrap_err(), "unknown argument \"\"");
+    }
+
+    #[test]
+    fn keeps_the_last_value() {
+        let args = parse(&["config=/a", "config=/b"]).unwrap();
+        assert_eq!(args.config.as_deref(), Some("/b"));
+    }
+}
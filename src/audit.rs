@@ -0,0 +1,540 @@
+//! A local record of every event as JSON Lines.
+//!
+//! Each line is the event as notifiers get it, plus an RFC 3339 `timestamp`,
+//! what happened to it as `outcome` and how each notifier fared as
+//! `deliveries`:
+//!
+//! ```text
+//! {"timestamp":"2022-07-06T17:37:00Z","id":"1657129020-4711-1","kind":"login","user":"alice",...,"outcome":"spooled","deliveries":{"ops":"sent","phone":"spooled"}}
+//! ```
+//!
+//! Events sent from a detached process are first recorded as `pending`
+//! before it starts, so they are in the log even if it is killed at the
+//! `[delivery]` timeout, and again with how delivery went.
+//!
+//! The log is rotated to `file.1`, `file.2` and so on once it reaches
+//! `max_size`.
+//!
+//! With a `key`, the log is tamper-evident: every record has a `prev` field
+//! with the SHA-256 of the line before it, across rotations, and ends with an
+//! `hmac` field, the HMAC-SHA256 of the line up to that field with the key.
+//! Editing, reordering or removing records breaks the chain, which [verify]
+//! reports. Removing records from the start of the oldest file can't be told
+//! apart from rotation.
+
+use crate::{
+    config::AuditConfig,
+    crypto::{hex, hmac_sha256, sha256, unhex, verify_hmac_sha256},
+    event::Event,
+    state,
+    template::utc_time,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+/// What a signed line ends with, before the hex HMAC and `"}`.
+const HMAC_FIELD: &[u8] = b",\"hmac\":\"";
+/// The `prev` of the first record in a chain.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// How far from the end of a log to look for its last line.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// What happened to an event with one notifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    Sent,
+    /// Sending failed and the message was spooled to be retried.
+    Spooled,
+    /// Sending failed and the message couldn't be spooled either.
+    Failed,
+}
+
+/// How each notifier fared with an event, by notifier name.
+pub type Deliveries = BTreeMap<String, Delivery>;
+
+/// What happened to an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// It was handed to the detached process that sends it. Another record
+    /// with how that went follows, unless the process was killed at the
+    /// `[delivery]` timeout first.
+    Pending,
+    /// Every notifier got it.
+    Sent,
+    /// Some notifiers only have it in the spool.
+    Spooled,
+    /// Some notifiers will never get it.
+    Failed,
+    /// The `[filter]` rules or the `only_remote` argument left it out.
+    Filtered,
+    /// It was counted towards a summary instead of being sent.
+    RateLimited,
+    /// There were no notifiers to send it to.
+    NoNotifiers,
+}
+
+impl Outcome {
+    /// The outcome of an event that was handed to notifiers.
+    pub fn of(deliveries: &Deliveries) -> Outcome {
+        if deliveries.is_empty() {
+            Outcome::NoNotifiers
+        } else if deliveries.values().any(|&d| d == Delivery::Failed) {
+            Outcome::Failed
+        } else if deliveries.values().any(|&d| d == Delivery::Spooled) {
+            Outcome::Spooled
+        } else {
+            Outcome::Sent
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prev: Option<String>,
+    #[serde(flatten)]
+    event: &'a Event,
+    outcome: Outcome,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    deliveries: &'a Deliveries,
+}
+
+/// Appends a record of `event` to the audit log, if there is one.
+pub fn write(
+    config: &AuditConfig,
+    event: &Event,
+    outcome: Outcome,
+    deliveries: &Deliveries,
+) -> io::Result<()> {
+    let path = match &config.file {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let _lock = state::lock(&sibling(path, ".", ".lock"))?;
+    let mut record = Record {
+        timestamp: utc_time(event.time),
+        prev: None,
+        event,
+        outcome,
+        deliveries,
+    };
+    let mut line = encode(config, path, &mut record)?;
+    if config.max_size > 0 {
+        let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > config.max_size {
+            rotate(path, config.keep)?;
+            // The line before may be gone now.
+            line = encode(config, path, &mut record)?;
+        }
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(&line)?;
+    if config.fsync {
+        file.sync_data()?;
+    }
+
+    Ok(())
+}
+
+/// Serializes `record` as the next line of the log at `path`, chained and
+/// signed if there is a key.
+fn encode(config: &AuditConfig, path: &Path, record: &mut Record<'_>) -> io::Result<Vec<u8>> {
+    let mut line = match &config.key {
+        Some(key) => {
+            let prev = last_line(path)?.map(|line| hex(&sha256(&line)));
+            record.prev = Some(prev.unwrap_or_else(|| GENESIS.to_string()));
+            sign(key.as_bytes(), serde_json::to_vec(record)?)
+        }
+        None => serde_json::to_vec(record)?,
+    };
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Appends the `hmac` field to the JSON object `line`.
+fn sign(key: &[u8], mut line: Vec<u8>) -> Vec<u8> {
+    let tag = hex(&hmac_sha256(key, &line));
+
+    line.pop();
+    line.extend_from_slice(HMAC_FIELD);
+    line.extend_from_slice(tag.as_bytes());
+    line.extend_from_slice(b"\"}");
+    line
+}
+
+/// The last line of the log at `path`, or of the one it was rotated to if it
+/// was just rotated.
+fn last_line(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    for path in [path.to_path_buf(), sibling(path, "", ".1")] {
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => continue,
+            Err(why) => return Err(why),
+        };
+        let len = file.metadata()?.len();
+        if len == 0 {
+            continue;
+        }
+
+        file.seek(SeekFrom::Start(len.saturating_sub(MAX_LINE)))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        if tail.last() == Some(&b'\n') {
+            tail.pop();
+        }
+        let start = tail.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        return Ok(Some(tail.split_off(start)));
+    }
+
+    Ok(None)
+}
+
+/// Where [verify] found the chain broken.
+#[derive(Debug)]
+pub struct Broken {
+    pub file: PathBuf,
+    /// The line number, counting from 1.
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Broken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.reason)
+    }
+}
+
+/// The existing log files for `config`, oldest first.
+pub fn files(config: &AuditConfig) -> Vec<PathBuf> {
+    let path = match &config.file {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+
+    let mut files: Vec<PathBuf> = (1..=config.keep)
+        .rev()
+        .map(|n| sibling(path, "", &format!(".{}", n)))
+        .chain([path.clone()])
+        .collect();
+    files.retain(|file| file.exists());
+    files
+}
+
+/// Checks the HMAC of every record in `files`, which are read in order, and
+/// that each record follows the one before it. Returns how many records were
+/// checked, or where the chain is first broken.
+pub fn verify(key: &[u8], files: &[PathBuf]) -> io::Result<Result<usize, Broken>> {
+    let mut prev: Option<String> = None;
+    let mut records = 0;
+
+    for file in files {
+        let data = fs::read(file)?;
+        for (idx, line) in data.split(|&b| b == b'\n').enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let broken = |reason| {
+                Ok(Err(Broken {
+                    file: file.clone(),
+                    line: idx + 1,
+                    reason,
+                }))
+            };
+
+            let (body, tag) = match split_signed(line) {
+                Some(parts) => parts,
+                None => return broken("record isn't signed"),
+            };
+            if unhex(tag).is_none_or(|tag| !verify_hmac_sha256(key, &body, &tag)) {
+                return broken("HMAC doesn't match");
+            }
+
+            let claimed = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|record| record["prev"].as_str().map(str::to_string));
+            match (claimed, &prev) {
+                (None, _) => return broken("record has no previous hash"),
+                (Some(claimed), Some(prev)) if claimed != *prev => {
+                    return broken("previous hash doesn't match the record before")
+                }
+                _ => {}
+            }
+
+            prev = Some(hex(&sha256(line)));
+            records += 1;
+        }
+    }
+
+    Ok(Ok(records))
+}
+
+/// Splits a signed line into the JSON object it was signed as and its hex
+/// HMAC.
+fn split_signed(line: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let line = line.strip_suffix(b"\"}")?;
+    let start = line
+        .windows(HMAC_FIELD.len())
+        .rposition(|window| window == HMAC_FIELD)?;
+
+    let mut body = line[..start].to_vec();
+    body.push(b'}');
+    Some((body, &line[start + HMAC_FIELD.len()..]))
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping the oldest, and moves
+/// `path` to `path.1`. With `keep` at 0 the log is just removed.
+fn rotate(path: &Path, keep: u32) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+
+    for n in (1..keep).rev() {
+        let from = sibling(path, "", &format!(".{}", n));
+        if from.exists() {
+            fs::rename(&from, sibling(path, "", &format!(".{}", n + 1)))?;
+        }
+    }
+    fs::rename(path, sibling(path, "", ".1"))
+}
+
+/// `path` with its file name wrapped in `prefix` and `suffix`.
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(prefix);
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EventKind;
+
+    const KEY: &str = "correct horse battery staple";
+
+    fn config(name: &str, key: Option<&str>) -> AuditConfig {
+        let dir =
+            std::env::temp_dir().join(format!("pam_rc2022-audit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        AuditConfig {
+            file: Some(dir.join("audit.log")),
+            max_size: 0,
+            keep: 5,
+            fsync: false,
+            key: key.map(str::to_string),
+        }
+    }
+
+    fn event(user: &str) -> Event {
+        Event {
+            id: "1657129020-4711-1".to_string(),
+            kind: EventKind::Login,
+            user: user.to_string(),
+            rhost: "192.0.2.1".to_string(),
+            ruser: String::new(),
+            service: "sshd".to_string(),
+            tty: "pts/3".to_string(),
+            hostname: "example".to_string(),
+            time: 1657129020,
+            session: None,
+            duration: None,
+            count: None,
+            lock: None,
+        }
+    }
+
+    fn write_all(config: &AuditConfig, users: &[&str]) {
+        let deliveries = Deliveries::from([("ops".to_string(), Delivery::Sent)]);
+        for user in users {
+            write(config, &event(user), Outcome::Sent, &deliveries).unwrap();
+        }
+    }
+
+    fn lines(config: &AuditConfig) -> Vec<String> {
+        let log = fs::read_to_string(config.file.as_ref().unwrap()).unwrap();
+        log.lines().map(str::to_string).collect()
+    }
+
+    fn set_lines(config: &AuditConfig, lines: &[String]) {
+        fs::write(config.file.as_ref().unwrap(), lines.join("\n") + "\n").unwrap();
+    }
+
+    fn broken(config: &AuditConfig) -> (usize, &'static str) {
+        match verify(KEY.as_bytes(), &files(config)).unwrap() {
+            Ok(records) => panic!("{} records verified", records),
+            Err(broken) => (broken.line, broken.reason),
+        }
+    }
+
+    #[test]
+    fn verifies_what_it_wrote() {
+        let config = config("round-trip", Some(KEY));
+        write_all(&config, &["alice", "bob", "carol"]);
+        assert_eq!(verify(KEY.as_bytes(), &files(&config)).unwrap().unwrap(), 3);
+
+        let lines = lines(&config);
+        let first: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(first["timestamp"], "2022-07-06T17:37:00Z");
+        assert_eq!(first["prev"], GENESIS);
+        assert_eq!(first["user"], "alice");
+        assert_eq!(first["outcome"], "sent");
+        assert_eq!(first["deliveries"]["ops"], "sent");
+        let second: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(second["prev"], hex(&sha256(lines[0].as_bytes())));
+
+        assert!(matches!(
+            verify(b"wrong key", &files(&config)).unwrap(),
+            Err(Broken {
+                line: 1,
+                reason: "HMAC doesn't match",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn continues_the_chain_across_rotations() {
+        // Every record is the same length, so find out what that is.
+        let probe = config("rotation-probe", Some(KEY));
+        write_all(&probe, &["alice"]);
+        let len = fs::metadata(probe.file.as_ref().unwrap()).unwrap().len();
+
+        // Room for two records and most of a third, which the signature
+        // pushes over the limit.
+        let mut config = config("rotation", Some(KEY));
+        config.max_size = 3 * len - 1;
+        write_all(&config, &["alice"; 5]);
+
+        let files = files(&config);
+        assert_eq!(files.len(), 3);
+        for file in &files {
+            assert!(fs::metadata(file).unwrap().len() <= config.max_size);
+        }
+        assert_eq!(verify(KEY.as_bytes(), &files).unwrap().unwrap(), 5);
+
+        // Dropping the newest rotated file breaks the chain in the next one.
+        let broken = verify(KEY.as_bytes(), &[files[0].clone(), files[2].clone()])
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(broken.file, files[2]);
+        assert_eq!(broken.line, 1);
+    }
+
+    #[test]
+    fn keeps_only_as_many_rotated_logs_as_asked() {
+        let mut config = config("keep", Some(KEY));
+        config.max_size = 1;
+        config.keep = 2;
+        write_all(&config, &["a", "b", "c", "d", "e"]);
+        assert_eq!(files(&config).len(), 3);
+        // The oldest records are gone, which can't be told from rotation.
+        assert_eq!(verify(KEY.as_bytes(), &files(&config)).unwrap().unwrap(), 3);
+    }
+
+    #[test]
+    fn detects_edited_records() {
+        let config = config("edited", Some(KEY));
+        write_all(&config, &["alice", "bob", "carol"]);
+        let mut lines = lines(&config);
+        lines[1] = lines[1].replace("\"bob\"", "\"eve\"");
+        set_lines(&config, &lines);
+        assert_eq!(broken(&config), (2, "HMAC doesn't match"));
+    }
+
+    #[test]
+    fn detects_reordered_records() {
+        let config = config("reordered", Some(KEY));
+        write_all(&config, &["alice", "bob", "carol"]);
+        let mut lines = lines(&config);
+        lines.swap(1, 2);
+        set_lines(&config, &lines);
+        assert_eq!(
+            broken(&config),
+            (2, "previous hash doesn't match the record before")
+        );
+    }
+
+    #[test]
+    fn detects_removed_records() {
+        let config = config("removed", Some(KEY));
+        write_all(&config, &["alice", "bob", "carol"]);
+        let mut lines = lines(&config);
+        lines.remove(1);
+        set_lines(&config, &lines);
+        assert_eq!(
+            broken(&config),
+            (2, "previous hash doesn't match the record before")
+        );
+    }
+
+    #[test]
+    fn detects_a_truncated_tail() {
+        let config = config("truncated", Some(KEY));
+        write_all(&config, &["alice", "bob", "carol"]);
+        let path = config.file.as_ref().unwrap();
+        let mut log = fs::read(path).unwrap();
+        log.truncate(log.len() - 10);
+        fs::write(path, log).unwrap();
+        assert_eq!(broken(&config), (3, "record isn't signed"));
+    }
+
+    #[test]
+    fn writes_unsigned_records_without_a_key() {
+        let config = config("unsigned", None);
+        write_all(&config, &["alice", "bob"]);
+        for line in lines(&config) {
+            let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert!(record.get("prev").is_none());
+            assert!(record.get("hmac").is_none());
+        }
+        assert_eq!(broken(&config), (1, "record isn't signed"));
+    }
+
+    #[test]
+    fn writes_nothing_without_a_file() {
+        let config = AuditConfig::default();
+        write(&config, &event("alice"), Outcome::Sent, &Deliveries::new()).unwrap();
+        assert!(files(&config).is_empty());
+    }
+
+    #[test]
+    fn outcome_of_deliveries() {
+        let deliveries = |list: &[Delivery]| -> Deliveries {
+            list.iter()
+                .enumerate()
+                .map(|(i, &d)| (i.to_string(), d))
+                .collect()
+        };
+        assert_eq!(Outcome::of(&deliveries(&[])), Outcome::NoNotifiers);
+        assert_eq!(
+            Outcome::of(&deliveries(&[Delivery::Sent, Delivery::Sent])),
+            Outcome::Sent
+        );
+        assert_eq!(
+            Outcome::of(&deliveries(&[Delivery::Sent, Delivery::Spooled])),
+            Outcome::Spooled
+        );
+        assert_eq!(
+            Outcome::of(&deliveries(&[Delivery::Failed, Delivery::Spooled])),
+            Outcome::Failed
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Companion command for pam_rc2022.
+//!
+//! ```text
+//! pam_rc2022ctl [-c CONFIG] [-v] flush
+//! pam_rc2022ctl [-c CONFIG] verify
+//! pam_rc2022ctl [-c CONFIG] lockouts
+//! pam_rc2022ctl [-c CONFIG] unlock KEY... | --all
+//! ```
+
+use pam_rc2022::{
+    audit::{self, Outcome},
+    config::{self, Config},
+    deliver,
+    event::{format_duration, now},
+    expired_summaries, lockout,
+    log::Level,
+    notifier, spool,
+};
+use std::{env, io, path::Path, process::exit};
+
+const USAGE: &str = "usage: pam_rc2022ctl [-c CONFIG] [-v] COMMAND
+
+commands:
+    flush    send failed login and rate limited login summaries that are due
+             and retry every spooled message that is due
+    verify   check the hash chain and HMACs of the audit log
+    lockouts list users and remote hosts with failed logins or a lockout
+    unlock KEY... | --all
+             clear the failed logins and lockout of each KEY, like user:alice
+             or rhost:192.0.2.1, or of everyone";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut config_path = config::DEFAULT_CONFIG_PATH.to_string();
+    let mut command = None;
+    let mut operands = Vec::new();
+    let mut verbose = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => match args.next() {
+                Some(path) => config_path = path,
+                None => usage("-c needs a path"),
+            },
+            "-v" | "--verbose" => verbose = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if command.is_none() => command = Some(arg),
+            _ => operands.push(arg),
+        }
+    }
+
+    let config = Config::load(Path::new(&config_path))
+        .unwrap_or_else(|why| die(&format!("can't load {}: {}", config_path, why)));
+
+    let no_operands = || {
+        if let Some(operand) = operands.first() {
+            usage(&format!("unexpected argument {:?}", operand));
+        }
+    };
+    match command.as_deref() {
+        Some("flush") => {
+            no_operands();
+            flush(&config, verbose)
+        }
+        Some("verify") => {
+            no_operands();
+            verify(&config)
+        }
+        Some("lockouts") => {
+            no_operands();
+            lockouts(&config)
+        }
+        Some("unlock") => unlock(&config, &operands),
+        Some(command) => usage(&format!("unknown command {:?}", command)),
+        None => usage("no command given"),
+    }
+}
+
+fn flush(config: &Config, verbose: bool) {
+    let mut log = |level: Level, msg: String| {
+        if verbose || level != Level::Debug {
+            eprintln!("pam_rc2022ctl: {}: {}", level, msg);
+        }
+    };
+
+    match expired_summaries(config) {
+        Ok(events) if !events.is_empty() => {
+            let notifiers = notifier::from_config(config);
+            let deliveries = deliver(config, &notifiers, &events, &mut log);
+            for (event, deliveries) in events.iter().zip(&deliveries) {
+                let outcome = Outcome::of(deliveries);
+                if let Err(why) = audit::write(&config.audit, event, outcome, deliveries) {
+                    eprintln!("pam_rc2022ctl: can't write audit log: {}", why);
+                }
+            }
+            println!("sent {} summaries", events.len());
+        }
+        Ok(_) => {}
+        Err(why) => eprintln!("pam_rc2022ctl: can't read summary state: {}", why),
+    }
+
+    match spool::flush(&config.spool, &config.http, &mut log) {
+        Ok(stats) => println!(
+            "sent {}, failed {}, waiting {}, dropped {}",
+            stats.sent, stats.failed, stats.waiting, stats.dropped
+        ),
+        Err(why) if why.kind() == io::ErrorKind::WouldBlock => {
+            die("the spool is already being flushed")
+        }
+        Err(why) => die(&format!(
+            "can't flush {}: {}",
+            config.spool.dir.display(),
+            why
+        )),
+    }
+}
+
+fn verify(config: &Config) {
+    let key = match &config.audit.key {
+        Some(key) => key,
+        None => die("the audit log has no key to verify it with"),
+    };
+    let files = audit::files(&config.audit);
+    if files.is_empty() {
+        die("there is no audit log");
+    }
+
+    match audit::verify(key.as_bytes(), &files) {
+        Ok(Ok(records)) => println!("{} records in {} files are intact", records, files.len()),
+        Ok(Err(broken)) => die(&format!("chain broken at {}", broken)),
+        Err(why) => die(&format!("can't read the audit log: {}", why)),
+    }
+}
+
+fn lockouts(config: &Config) {
+    let now = now();
+    let entries = lockout::list(config, now).unwrap_or_else(|why| {
+        die(&format!(
+            "can't read {}: {}",
+            config.state_dir.display(),
+            why
+        ))
+    });
+
+    for (key, entry) in entries {
+        match entry.locked_until {
+            Some(until) => println!(
+                "{}\tlocked for {}",
+                key,
+                format_duration(until.saturating_sub(now))
+            ),
+            None => println!("{}\t{} failures", key, entry.failures.len()),
+        }
+    }
+}
+
+fn unlock(config: &Config, keys: &[String]) {
+    let cleared = match keys {
+        [] => usage("unlock needs a key or --all"),
+        [all] if all == "--all" => lockout::clear(config, None),
+        _ => keys.iter().try_fold(0, |cleared, key| {
+            if !key.contains(':') {
+                usage(&format!("{:?} isn't like user:NAME or rhost:HOST", key));
+            }
+            Ok(cleared + lockout::clear(config, Some(key))?)
+        }),
+    }
+    .unwrap_or_else(|why| {
+        die(&format!(
+            "can't update {}: {}",
+            config.state_dir.display(),
+            why
+        ))
+    });
+
+    println!("cleared {}", cleared);
+}
+
+fn die(msg: &str) -> ! {
+    eprintln!("pam_rc2022ctl: {}", msg);
+    exit(1)
+}
+
+fn usage(msg: &str) -> ! {
+    eprintln!("pam_rc2022ctl: {}", msg);
+    eprintln!("{}", USAGE);
+    exit(2)
+}
@@ -0,0 +1,1149 @@
+//! Runtime configuration for the module.
+//!
+//! The configuration file holds webhook URLs, which are secrets, so it is only
+//! read if it is owned by root and not accessible by anyone else.
+
+use crate::{
+    access::Access,
+    filter::Filter,
+    template::{Escape, Templates},
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Where the configuration is read from unless `config=` is passed as a module
+/// argument.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/security/pam_rc2022.conf";
+
+/// The parsed configuration file.
+///
+/// The file format is a list of `key = value` lines, optionally grouped into
+/// `[section]`s. Blank lines and lines starting with `#` are ignored. Keys may
+/// be repeated where noted.
+///
+/// ```text
+/// # Discord webhook to send login messages to, may be given more than once.
+/// webhook = https://discord.com/api/webhooks/...
+///
+/// [notifier.ops]
+/// type = slack
+/// url = https://hooks.slack.com/services/...
+/// ```
+#[derive(Debug)]
+pub struct Config {
+    /// Discord webhook URLs that every message is sent to.
+    pub webhooks: Vec<String>,
+    /// Only use the `[notifier.NAME]` sections named here, instead of all of them.
+    pub enabled_notifiers: Option<Vec<String>>,
+    /// The `[notifier.NAME]` sections, in the order they appear in the file.
+    pub notifiers: Vec<NotifierConfig>,
+    /// The `[delivery]` section.
+    pub delivery: DeliveryConfig,
+    /// The `[spool]` section.
+    pub spool: SpoolConfig,
+    /// The `[http]` section.
+    pub http: HttpConfig,
+    /// The `[auth_fail]` section.
+    pub auth_fail: AuthFailConfig,
+    /// The `[rate_limit]` section.
+    pub rate_limit: RateLimitConfig,
+    /// The `[audit]` section.
+    pub audit: AuditConfig,
+    /// The `[totp]` section.
+    pub totp: TotpConfig,
+    /// The `[approve]` section.
+    pub approve: ApproveConfig,
+    /// The `[lockout]` section.
+    pub lockout: LockoutConfig,
+    /// The `[templates]` section, with a message template for each kind of
+    /// event, such as `login = {user} logged in to {hostname}`.
+    pub templates: Templates,
+    /// The `[filter]` section, deciding which events are notified about.
+    pub filter: Filter,
+    /// The `[access]` section, deciding who may log in.
+    pub access: Access,
+    /// `diagnostics`: where problems with the module itself are reported.
+    pub diagnostics: Diagnostics,
+    /// `state_dir`: where state that has to survive between logins, such as
+    /// failure counters, is kept.
+    pub state_dir: PathBuf,
+    /// The line `notifiers` was set on, for error messages.
+    enabled_notifiers_line: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            webhooks: Vec::new(),
+            enabled_notifiers: None,
+            notifiers: Vec::new(),
+            delivery: DeliveryConfig::default(),
+            spool: SpoolConfig::default(),
+            http: HttpConfig::default(),
+            auth_fail: AuthFailConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            audit: AuditConfig::default(),
+            totp: TotpConfig::default(),
+            approve: ApproveConfig::default(),
+            lockout: LockoutConfig::default(),
+            templates: Templates::default(),
+            filter: Filter::default(),
+            access: Access::default(),
+            diagnostics: Diagnostics::default(),
+            state_dir: PathBuf::from("/var/lib/pam_rc2022"),
+            enabled_notifiers_line: 0,
+        }
+    }
+}
+
+/// Where problems with the module itself, such as a notifier that can't be
+/// reached, are reported.
+///
+/// Telling the user reveals that logins are being watched, so by default only
+/// syslog hears about them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Diagnostics {
+    #[default]
+    Syslog,
+    /// The user's terminal, unless `PAM_SILENT` or the `silent` module
+    /// argument is given.
+    User,
+    Both,
+}
+
+impl Diagnostics {
+    fn parse(value: &str) -> Result<Diagnostics, String> {
+        Ok(match value {
+            "syslog" => Diagnostics::Syslog,
+            "user" => Diagnostics::User,
+            "both" => Diagnostics::Both,
+            _ => return Err(format!("unknown diagnostics {:?}", value)),
+        })
+    }
+
+    /// Returns true if syslog hears about problems.
+    pub fn syslog(self) -> bool {
+        self != Diagnostics::User
+    }
+
+    /// Returns true if the user hears about problems.
+    pub fn user(self) -> bool {
+        self != Diagnostics::Syslog
+    }
+}
+
+/// The `[delivery]` section, which controls how messages are sent.
+#[derive(Debug)]
+pub struct DeliveryConfig {
+    /// `timeout`: how long the detached sender process may run before it is
+    /// killed.
+    pub timeout: Duration,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        DeliveryConfig {
+            timeout: crate::detach::DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl DeliveryConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "timeout" => self.timeout = parse_duration(value)?,
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[spool]` section, which controls where messages that couldn't be sent
+/// are kept until they can be.
+#[derive(Debug)]
+pub struct SpoolConfig {
+    /// `enabled`: whether failed messages are spooled at all.
+    pub enabled: bool,
+    /// `dir`: the spool directory. It is created with mode 0700 if it doesn't
+    /// exist.
+    pub dir: PathBuf,
+    /// `max_entries`: how many messages to keep. The oldest are dropped first.
+    pub max_entries: usize,
+    /// `retry`: how long to wait before the first retry. Every further retry
+    /// waits twice as long as the one before.
+    pub retry: Duration,
+    /// `max_retry`: the longest to wait between retries.
+    pub max_retry: Duration,
+    /// `max_age`: how long to keep retrying a message before giving up.
+    pub max_age: Duration,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        SpoolConfig {
+            enabled: true,
+            dir: PathBuf::from("/var/spool/pam_rc2022"),
+            max_entries: 1000,
+            retry: Duration::from_secs(60),
+            max_retry: Duration::from_secs(6 * 60 * 60),
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl SpoolConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "enabled" => self.enabled = parse_bool(value)?,
+            "dir" => self.dir = PathBuf::from(value),
+            "max_entries" => {
+                self.max_entries = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid max_entries {:?}", value))?
+            }
+            "retry" => self.retry = parse_duration(value)?,
+            "max_retry" => self.max_retry = parse_duration(value)?,
+            "max_age" => self.max_age = parse_duration(value)?,
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[http]` section, which controls how the HTTP client connects.
+#[derive(Debug)]
+pub struct HttpConfig {
+    /// `connect_timeout`: how long to wait for a connection to be made.
+    pub connect_timeout: Duration,
+    /// `timeout`: how long a whole request may take, which can't be 0.
+    pub timeout: Duration,
+    /// `proxy`: the proxy to connect through, such as `http://proxy:3128`.
+    /// Without this the usual `https_proxy` environment variables are used.
+    pub proxy: Option<String>,
+    /// `ca_file`: a PEM bundle of CA certificates to trust instead of the
+    /// system ones.
+    pub ca_file: Option<PathBuf>,
+    /// `pinned_public_key`: the server's public key must match this, either a
+    /// path to a PEM or DER file or `sha256//` followed by base64 hashes
+    /// separated by `;`.
+    pub pinned_public_key: Option<String>,
+    /// `client_cert`: a PEM client certificate to present for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    /// `client_key`: the private key for `client_cert`, if it isn't in the
+    /// same file.
+    pub client_key: Option<PathBuf>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+            proxy: None,
+            ca_file: None,
+            pinned_public_key: None,
+            client_cert: None,
+            client_key: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "connect_timeout" => self.connect_timeout = parse_duration(value)?,
+            "timeout" => {
+                self.timeout = parse_duration(value)?;
+                // curl would take it as no timeout at all.
+                if self.timeout.is_zero() {
+                    return Err("timeout can't be 0".to_string());
+                }
+            }
+            "proxy" => self.proxy = Some(value.to_string()),
+            "ca_file" => self.ca_file = Some(PathBuf::from(value)),
+            "pinned_public_key" => self.pinned_public_key = Some(value.to_string()),
+            "client_cert" => self.client_cert = Some(PathBuf::from(value)),
+            "client_key" => self.client_key = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[auth_fail]` section, which controls how failed authentications are
+/// reported.
+#[derive(Debug)]
+pub struct AuthFailConfig {
+    /// `window`: after a failure from a remote host is reported, further
+    /// failures from it are only counted for this long and then reported as
+    /// one summary.
+    pub window: Duration,
+}
+
+impl Default for AuthFailConfig {
+    fn default() -> Self {
+        AuthFailConfig {
+            window: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl AuthFailConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "window" => self.window = parse_duration(value)?,
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[rate_limit]` section, which stops frequent logins such as those of
+/// automation accounts from flooding notifiers.
+#[derive(Debug, Default)]
+pub struct RateLimitConfig {
+    /// `window`: after a login is reported, further logins by the same user
+    /// from the same remote host via the same service are only counted for
+    /// this long and then reported as one summary. Their logouts aren't
+    /// reported at all. `None`, the default, reports every login.
+    pub window: Option<Duration>,
+}
+
+impl RateLimitConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "window" => {
+                self.window = Some(parse_duration(value)?).filter(|window| !window.is_zero())
+            }
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[audit]` section, which keeps a local record of every event as JSON
+/// Lines, whether or not it could be delivered.
+#[derive(Debug)]
+pub struct AuditConfig {
+    /// `file`: where the log is written. `None`, the default, keeps no log.
+    pub file: Option<PathBuf>,
+    /// `max_size`: the size in bytes at which the log is rotated, or 0 to let
+    /// it grow forever.
+    pub max_size: u64,
+    /// `keep`: how many rotated logs to keep, as `file.1`, `file.2`...
+    pub keep: u32,
+    /// `fsync`: whether every record is synced to disk before the module
+    /// carries on.
+    pub fsync: bool,
+    /// `key`: if set, every record carries the hash of the record before it
+    /// and an HMAC-SHA256 with this key, see [crate::audit::verify].
+    pub key: Option<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            file: None,
+            max_size: 10 * 1024 * 1024,
+            keep: 5,
+            fsync: false,
+            key: None,
+        }
+    }
+}
+
+impl AuditConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "file" => self.file = Some(PathBuf::from(value)),
+            "max_size" => self.max_size = parse_size(value)?,
+            "keep" => {
+                self.keep = value
+                    .parse()
+                    .map_err(|_| format!("invalid keep {:?}", value))?
+            }
+            "fsync" => self.fsync = parse_bool(value)?,
+            "key" => self.key = Some(value.to_string()),
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[totp]` section, which controls the second factor asked for with the
+/// `totp` module argument.
+#[derive(Debug)]
+pub struct TotpConfig {
+    /// `secrets_dir`: a root owned directory with a secret file for each user,
+    /// named after them. It is looked in before `user_file`.
+    pub secrets_dir: Option<PathBuf>,
+    /// `user_file`: where users keep their own secret file, relative to their
+    /// home directory. `None` only uses `secrets_dir`.
+    pub user_file: Option<PathBuf>,
+    /// `digits`: how long codes are, 6 to 8.
+    pub digits: u32,
+    /// `period`: how long each code is valid for.
+    pub period: Duration,
+    /// `skew`: how many periods either side of now codes are accepted from,
+    /// for clocks that are a little off.
+    pub skew: u32,
+    /// `prompt`: what the user is asked.
+    pub prompt: String,
+    /// `nullok`: let users without a secret file through instead of failing
+    /// them.
+    pub nullok: bool,
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        TotpConfig {
+            secrets_dir: None,
+            user_file: Some(PathBuf::from(".config/pam_rc2022/totp")),
+            digits: 6,
+            period: Duration::from_secs(30),
+            skew: 1,
+            prompt: "Verification code: ".to_string(),
+            nullok: false,
+        }
+    }
+}
+
+impl TotpConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "secrets_dir" => self.secrets_dir = Some(PathBuf::from(value)),
+            "user_file" => {
+                self.user_file = Some(PathBuf::from(value)).filter(|_| !value.is_empty())
+            }
+            "digits" => {
+                self.digits = value
+                    .parse()
+                    .ok()
+                    .filter(|n| (6..=8).contains(n))
+                    .ok_or_else(|| format!("invalid digits {:?}", value))?
+            }
+            "period" => {
+                self.period = Some(parse_duration(value)?)
+                    .filter(|period| !period.is_zero())
+                    .ok_or_else(|| format!("invalid period {:?}", value))?
+            }
+            "skew" => {
+                self.skew = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n <= 10)
+                    .ok_or_else(|| format!("invalid skew {:?}", value))?
+            }
+            // Values are trimmed, so the space after the prompt is added back.
+            "prompt" => self.prompt = format!("{} ", value),
+            "nullok" => self.nullok = parse_bool(value)?,
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[approve]` section, which controls the out-of-band approval asked
+/// for with the `approve` module argument.
+#[derive(Debug)]
+pub struct ApproveConfig {
+    /// `url`: where decisions are polled from, with `{id}` replaced by the ID
+    /// of the approval request.
+    pub url: Option<String>,
+    /// `header`: extra `Name: value` headers for polling, may be given more
+    /// than once.
+    pub headers: Vec<String>,
+    /// `notifiers`: the `[notifier.NAME]` sections approval requests are sent
+    /// to, instead of every enabled notifier.
+    pub notifiers: Option<Vec<String>>,
+    /// `timeout`: how long to wait for a decision.
+    pub timeout: Duration,
+    /// `poll_interval`: how long to wait between polls that come back
+    /// undecided.
+    pub poll_interval: Duration,
+    /// `fail_open`: let the login through when no decision can be had,
+    /// instead of failing it.
+    pub fail_open: bool,
+    /// `message`: what the user is told while they wait.
+    pub message: String,
+    /// The line `notifiers` was set on, for error messages.
+    notifiers_line: usize,
+}
+
+impl Default for ApproveConfig {
+    fn default() -> Self {
+        ApproveConfig {
+            url: None,
+            headers: Vec::new(),
+            notifiers: None,
+            timeout: Duration::from_secs(2 * 60),
+            poll_interval: Duration::from_secs(2),
+            fail_open: false,
+            message: "Waiting for this login to be approved...".to_string(),
+            notifiers_line: 0,
+        }
+    }
+}
+
+impl ApproveConfig {
+    fn set(&mut self, key: &str, value: &str, line: usize) -> Result<(), String> {
+        match key {
+            "url" => self.url = Some(value.to_string()),
+            "header" => {
+                if !value.contains(':') {
+                    return Err(format!("expected Name: value, got {:?}", value));
+                }
+                self.headers.push(value.to_string())
+            }
+            "notifiers" => {
+                self.notifiers = Some(split_list(value));
+                self.notifiers_line = line;
+            }
+            "timeout" => self.timeout = parse_duration(value)?,
+            "poll_interval" => self.poll_interval = parse_duration(value)?,
+            "fail_open" => self.fail_open = parse_bool(value)?,
+            "message" => self.message = value.to_string(),
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `[lockout]` section, which locks users and remote hosts out after
+/// repeated failed authentications.
+#[derive(Debug)]
+pub struct LockoutConfig {
+    /// `deny`: how many failures within `interval` lead to a lock. `None`,
+    /// the default, never locks anyone.
+    pub deny: Option<u32>,
+    /// `interval`: how long failures count for.
+    pub interval: Duration,
+    /// `unlock_time`: how long a lock lasts.
+    pub unlock_time: Duration,
+    /// `track`: whether failures count against the user.
+    pub track_user: bool,
+    /// `track`: whether failures count against the remote host.
+    pub track_rhost: bool,
+    /// `even_deny_root`: lock root out too.
+    pub even_deny_root: bool,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            deny: None,
+            interval: Duration::from_secs(15 * 60),
+            unlock_time: Duration::from_secs(10 * 60),
+            track_user: true,
+            track_rhost: true,
+            even_deny_root: false,
+        }
+    }
+}
+
+impl LockoutConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "deny" => {
+                self.deny = value
+                    .parse()
+                    .map(|deny| Some(deny).filter(|&deny| deny > 0))
+                    .map_err(|_| format!("invalid deny {:?}", value))?
+            }
+            "interval" => self.interval = parse_duration(value)?,
+            "unlock_time" => self.unlock_time = parse_duration(value)?,
+            "track" => {
+                let items = split_list(value);
+                if let Some(item) = items.iter().find(|&item| item != "user" && item != "rhost") {
+                    return Err(format!("can only track user and rhost, not {:?}", item));
+                }
+                self.track_user = items.iter().any(|item| item == "user");
+                self.track_rhost = items.iter().any(|item| item == "rhost");
+            }
+            "even_deny_root" => self.even_deny_root = parse_bool(value)?,
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// The kinds of service a notifier can send messages to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifierKind {
+    Discord,
+    Slack,
+    Matrix,
+    Mattermost,
+    Gotify,
+    Ntfy,
+    /// A generic JSON POST of the event.
+    Json,
+}
+
+impl NotifierKind {
+    fn parse(value: &str) -> Result<NotifierKind, String> {
+        Ok(match value {
+            "discord" => NotifierKind::Discord,
+            "slack" => NotifierKind::Slack,
+            "matrix" => NotifierKind::Matrix,
+            "mattermost" => NotifierKind::Mattermost,
+            "gotify" => NotifierKind::Gotify,
+            "ntfy" => NotifierKind::Ntfy,
+            "json" => NotifierKind::Json,
+            _ => return Err(format!("unknown notifier type {:?}", value)),
+        })
+    }
+}
+
+/// A `[notifier.NAME]` section.
+///
+/// Which keys are required depends on the `type`:
+///
+/// | type         | url                         | also needs      |
+/// |--------------|-----------------------------|-----------------|
+/// | `discord`    | webhook URL                 |                 |
+/// | `slack`      | incoming webhook URL        |                 |
+/// | `mattermost` | incoming webhook URL        |                 |
+/// | `matrix`     | homeserver base URL         | `token`, `room` |
+/// | `gotify`     | server base URL             | `token`         |
+/// | `ntfy`       | topic URL                   |                 |
+/// | `json`       | any URL                     |                 |
+#[derive(Debug)]
+pub struct NotifierConfig {
+    pub name: String,
+    pub kind: NotifierKind,
+    pub url: String,
+    /// Access token for Matrix, application token for Gotify, or bearer token
+    /// for ntfy.
+    pub token: Option<String>,
+    /// Matrix room ID, such as `!abcdef:example.org`.
+    pub room: Option<String>,
+    /// Channel override for Slack and Mattermost.
+    pub channel: Option<String>,
+    /// Username override for Discord, Slack and Mattermost.
+    pub username: Option<String>,
+    /// Avatar override for Discord, icon override for Slack and Mattermost.
+    pub avatar_url: Option<String>,
+    /// Send Discord messages as rich embeds instead of plain text.
+    pub embed: bool,
+    /// Message priority for Gotify and ntfy.
+    pub priority: Option<u8>,
+    /// Extra `Name: value` headers for `json`, may be given more than once.
+    pub headers: Vec<String>,
+    /// `template.EVENT`: message templates that override the `[templates]`
+    /// section for this notifier.
+    pub templates: Templates,
+    /// `escape`: how values in templates are escaped. The default depends on
+    /// the type.
+    pub escape: Option<Escape>,
+    /// `signing_secret`: sign every request with this secret, see
+    /// [crate::http::Signing].
+    pub signing_secret: Option<String>,
+    /// `signing_header`: the header the signature goes in.
+    pub signing_header: String,
+    /// The line the section starts on, for error messages.
+    line: usize,
+    /// Whether `type` was set, since there is no sensible default.
+    has_kind: bool,
+}
+
+impl NotifierConfig {
+    fn new(name: &str, line: usize) -> NotifierConfig {
+        NotifierConfig {
+            name: name.to_string(),
+            kind: NotifierKind::Json,
+            url: String::new(),
+            token: None,
+            room: None,
+            channel: None,
+            username: None,
+            avatar_url: None,
+            embed: false,
+            priority: None,
+            headers: Vec::new(),
+            templates: Templates::default(),
+            escape: None,
+            signing_secret: None,
+            signing_header: "X-Signature".to_string(),
+            line,
+            has_kind: false,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "type" => {
+                self.kind = NotifierKind::parse(value)?;
+                self.has_kind = true;
+            }
+            "url" => self.url = value.to_string(),
+            "token" => self.token = Some(value.to_string()),
+            "room" => self.room = Some(value.to_string()),
+            "channel" => self.channel = Some(value.to_string()),
+            "username" => self.username = Some(value.to_string()),
+            "avatar_url" => self.avatar_url = Some(value.to_string()),
+            "embed" => self.embed = parse_bool(value)?,
+            "priority" => {
+                self.priority = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid priority {:?}", value))?,
+                )
+            }
+            "header" => {
+                if !value.contains(':') {
+                    return Err(format!("expected Name: value, got {:?}", value));
+                }
+                self.headers.push(value.to_string())
+            }
+            "escape" => self.escape = Some(Escape::parse(value)?),
+            "signing_secret" => self.signing_secret = Some(value.to_string()),
+            "signing_header" => {
+                if value.is_empty() || value.contains([':', ' ']) {
+                    return Err(format!("invalid header name {:?}", value));
+                }
+                self.signing_header = value.to_string()
+            }
+            _ => match key.strip_prefix("template.") {
+                Some(kind) => self.templates.set(kind, value)?,
+                None => return Err(format!("unknown key {:?}", key)),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.has_kind {
+            return Err(format!("notifier {:?} needs a type", self.name));
+        }
+        if self.url.is_empty() {
+            return Err(format!("notifier {:?} needs a url", self.name));
+        }
+        match self.kind {
+            NotifierKind::Matrix if self.token.is_none() || self.room.is_none() => Err(format!(
+                "matrix notifier {:?} needs a token and a room",
+                self.name
+            )),
+            NotifierKind::Gotify if self.token.is_none() => {
+                Err(format!("gotify notifier {:?} needs a token", self.name))
+            }
+            NotifierKind::Discord if self.signing_secret.is_some() && !self.embed => Err(format!(
+                "discord notifier {:?} can only be signed with embed = true",
+                self.name
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Everything that can go wrong when loading the configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Permissions(String),
+    Syntax { line: usize },
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(why) => write!(f, "{}", why),
+            ConfigError::Permissions(why) => write!(f, "insecure permissions: {}", why),
+            ConfigError::Syntax { line } => write!(f, "line {}: expected key = value", line),
+            ConfigError::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(why: io::Error) -> Self {
+        ConfigError::Io(why)
+    }
+}
+
+impl Config {
+    /// Reads and parses the configuration file at `path`.
+    ///
+    /// The file must be a regular file owned by root with no permission bits
+    /// set for group or others. The checks are done on the opened file so
+    /// the file can't be swapped out from under us between checking and reading.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let mut file = File::open(path)?;
+        let meta = file.metadata()?;
+
+        if !meta.is_file() {
+            return Err(ConfigError::Permissions(format!(
+                "{} is not a regular file",
+                path.display()
+            )));
+        }
+        if meta.uid() != 0 {
+            return Err(ConfigError::Permissions(format!(
+                "{} is not owned by root",
+                path.display()
+            )));
+        }
+        if meta.mode() & 0o077 != 0 {
+            return Err(ConfigError::Permissions(format!(
+                "{} has mode {:o}, expected 0600 or stricter",
+                path.display(),
+                meta.mode() & 0o7777
+            )));
+        }
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Config::parse(&contents)
+    }
+
+    /// Parses the contents of a configuration file.
+    pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut section = String::new();
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let result = match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                Some(name) => {
+                    section = name.trim().to_string();
+                    config.start_section(&section, idx + 1)
+                }
+                None => {
+                    let (key, value) = line
+                        .split_once('=')
+                        .ok_or(ConfigError::Syntax { line: idx + 1 })?;
+                    config.set(&section, key.trim(), value.trim(), idx + 1)
+                }
+            };
+            result.map_err(|message| ConfigError::Invalid {
+                line: idx + 1,
+                message,
+            })?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn start_section(&mut self, section: &str, line: usize) -> Result<(), String> {
+        if matches!(
+            section,
+            "delivery"
+                | "spool"
+                | "http"
+                | "auth_fail"
+                | "rate_limit"
+                | "audit"
+                | "totp"
+                | "approve"
+                | "lockout"
+                | "templates"
+                | "filter"
+                | "access"
+        ) {
+            return Ok(());
+        }
+
+        match section.split_once('.') {
+            Some(("notifier", name)) if !name.is_empty() => {
+                if self.notifiers.iter().any(|n| n.name == name) {
+                    return Err(format!("duplicate notifier {:?}", name));
+                }
+                self.notifiers.push(NotifierConfig::new(name, line));
+            }
+            _ => return Err(format!("unknown section {:?}", section)),
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: &str, line: usize) -> Result<(), String> {
+        if section.starts_with("notifier.") {
+            // start_section always pushes the notifier before its keys are set.
+            return self.notifiers.last_mut().unwrap().set(key, value);
+        }
+        match section {
+            "delivery" => return self.delivery.set(key, value),
+            "spool" => return self.spool.set(key, value),
+            "http" => return self.http.set(key, value),
+            "auth_fail" => return self.auth_fail.set(key, value),
+            "rate_limit" => return self.rate_limit.set(key, value),
+            "audit" => return self.audit.set(key, value),
+            "totp" => return self.totp.set(key, value),
+            "approve" => return self.approve.set(key, value, line),
+            "lockout" => return self.lockout.set(key, value),
+            "templates" => return self.templates.set(key, value),
+            "filter" => return self.filter.set(key, value),
+            "access" => return self.access.set(key, value),
+            _ => {}
+        }
+
+        match key {
+            "webhook" => self.webhooks.push(value.to_string()),
+            "state_dir" => self.state_dir = PathBuf::from(value),
+            "diagnostics" => self.diagnostics = Diagnostics::parse(value)?,
+            "notifiers" => {
+                self.enabled_notifiers = Some(split_list(value));
+                self.enabled_notifiers_line = line;
+            }
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        for notifier in &self.notifiers {
+            notifier
+                .validate()
+                .map_err(|message| ConfigError::Invalid {
+                    line: notifier.line,
+                    message,
+                })?;
+        }
+
+        let lists = [
+            (&self.enabled_notifiers, self.enabled_notifiers_line),
+            (&self.approve.notifiers, self.approve.notifiers_line),
+        ];
+        for (names, line) in lists {
+            for name in names.iter().flatten() {
+                if !self.notifiers.iter().any(|n| &n.name == name) {
+                    return Err(ConfigError::Invalid {
+                        line,
+                        message: format!("no [notifier.{}] section", name),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits a comma separated list, dropping empty items.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The longest duration accepted, which is far more than any setting needs
+/// and leaves room to add it to a timestamp.
+const MAX_DURATION: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// Parses a duration such as `30`, `30s`, `5m`, `2h` or `1d`. Plain numbers
+/// are seconds, and anything over ten years is rejected.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let multiplier = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid duration {:?}", value)),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|&secs| secs <= MAX_DURATION)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration {:?}", value))
+}
+
+/// Parses a size such as `4096`, `512k`, `10M` or `1G`. Plain numbers are
+/// bytes.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, ""),
+    };
+    let multiplier = match unit.trim() {
+        "" => 1,
+        "k" | "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size {:?}", value)),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {:?}", value))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expected true or false, got {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(contents: &str) -> (usize, String) {
+        match Config::parse(contents) {
+            Err(ConfigError::Invalid { line, message }) => (line, message),
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn durations_with_units() {
+        for (value, secs) in [
+            ("0", 0),
+            ("30", 30),
+            ("30s", 30),
+            ("5m", 5 * 60),
+            ("2h", 2 * 60 * 60),
+            ("1d", 24 * 60 * 60),
+            ("3650d", MAX_DURATION),
+        ] {
+            assert_eq!(
+                parse_duration(value),
+                Ok(Duration::from_secs(secs)),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for value in [
+            "",
+            "s",
+            "-1",
+            "1.5h",
+            "5 minutes",
+            "1w",
+            "3651d",
+            "999999999999999999d",
+            "99999999999999999999",
+        ] {
+            assert!(parse_duration(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512k"), Ok(512 * 1024));
+        assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("99999999999999999G").is_err());
+    }
+
+    #[test]
+    fn parses_sections_comments_and_blank_lines() {
+        let config = Config::parse(
+            "# top level\n\
+             state_dir = /tmp/state\n\
+             webhook = https://discord.example/a\n\
+             \n\
+             [delivery]\n\
+             timeout = 5m\n\
+             [ spool ]\n\
+             retry = 10\n",
+        )
+        .unwrap();
+        assert_eq!(config.state_dir, PathBuf::from("/tmp/state"));
+        assert_eq!(config.webhooks, ["https://discord.example/a"]);
+        assert_eq!(config.delivery.timeout, Duration::from_secs(300));
+        assert_eq!(config.spool.retry, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(
+            invalid("state_dir = /tmp\ncolour = blue\n"),
+            (2, "unknown key \"colour\"".to_string())
+        );
+        assert_eq!(
+            invalid("[delivery]\n\ntimeout = 5\nretries = 3\n"),
+            (4, "unknown key \"retries\"".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_sections() {
+        assert_eq!(
+            invalid("[deliveries]\n"),
+            (1, "unknown section \"deliveries\"".to_string())
+        );
+        assert_eq!(
+            invalid("[notifier.]\n"),
+            (1, "unknown section \"notifier.\"".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_invalid_values() {
+        let (line, message) = invalid("[delivery]\ntimeout = 999999999999999999d\n");
+        assert_eq!(line, 2);
+        assert!(message.contains("invalid duration"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_an_http_timeout_of_0() {
+        assert_eq!(
+            invalid("[http]\ntimeout = 0\n"),
+            (2, "timeout can't be 0".to_string())
+        );
+        assert_eq!(
+            Config::parse("[http]\ntimeout = 1\n").unwrap().http.timeout,
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn rejects_lines_without_equals() {
+        assert!(matches!(
+            Config::parse("[delivery]\ntimeout 5\n"),
+            Err(ConfigError::Syntax { line: 2 })
+        ));
+    }
+
+    #[test]
+    fn rejects_notifiers_that_dont_exist() {
+        let (line, message) = invalid("notifiers = ops\n");
+        assert_eq!(line, 1);
+        assert_eq!(message, "no [notifier.ops] section");
+    }
+}
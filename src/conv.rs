@@ -0,0 +1,231 @@
+//! Talking to the user through the application's conversation function, the
+//! `PAM_CONV` item.
+//!
+//! Several messages can be sent in one conversation, which lets graphical
+//! applications show them as one dialog:
+//!
+//! ```ignore
+//! let answers = pamh.converse(&[
+//!     Prompt::info("Your password has expired."),
+//!     Prompt::echo_off("New password: "),
+//! ])?;
+//! ```
+//!
+//! Answers are wiped from memory when they're dropped, both the copy handed
+//! out and the one the application allocated.
+
+use crate::{MessageStyle, Pam, PamItemType, PamResult, PamResultCode};
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+};
+use zeroize::{Zeroize, Zeroizing};
+
+/// What the user typed in answer to a prompt.
+pub type Answer = Zeroizing<String>;
+
+/// One message in a conversation.
+#[derive(Debug, Clone, Copy)]
+pub struct Prompt<'a> {
+    pub style: MessageStyle,
+    pub text: &'a str,
+}
+
+impl<'a> Prompt<'a> {
+    /// Asks for something secret, like a password, without echoing it.
+    pub fn echo_off(text: &'a str) -> Prompt<'a> {
+        Prompt {
+            style: MessageStyle::PAM_PROMPT_ECHO_OFF,
+            text,
+        }
+    }
+
+    /// Asks for something that can be shown as it is typed.
+    pub fn echo_on(text: &'a str) -> Prompt<'a> {
+        Prompt {
+            style: MessageStyle::PAM_PROMPT_ECHO_ON,
+            text,
+        }
+    }
+
+    /// Tells the user about a problem.
+    pub fn error(text: &'a str) -> Prompt<'a> {
+        Prompt {
+            style: MessageStyle::PAM_ERROR_MSG,
+            text,
+        }
+    }
+
+    /// Tells the user something.
+    pub fn info(text: &'a str) -> Prompt<'a> {
+        Prompt {
+            style: MessageStyle::PAM_TEXT_INFO,
+            text,
+        }
+    }
+
+    /// Whether the user is expected to answer.
+    pub fn is_question(&self) -> bool {
+        matches!(
+            self.style,
+            MessageStyle::PAM_PROMPT_ECHO_OFF | MessageStyle::PAM_PROMPT_ECHO_ON
+        )
+    }
+}
+
+/// The C layout of `struct pam_message`.
+#[repr(C)]
+struct RawMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+/// The C layout of `struct pam_response`.
+#[repr(C)]
+struct RawResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+/// The C layout of `struct pam_conv`.
+#[repr(C)]
+struct RawConv {
+    conv: Option<
+        unsafe extern "C" fn(
+            num_msg: c_int,
+            msg: *mut *const RawMessage,
+            resp: *mut *mut RawResponse,
+            appdata_ptr: *mut c_void,
+        ) -> c_int,
+    >,
+    appdata_ptr: *mut c_void,
+}
+
+impl<'a> Pam<'a> {
+    /// Has a conversation with the user, returning an answer for every
+    /// prompt. Answers to messages that aren't questions are `None`, as are
+    /// those the application didn't give.
+    ///
+    /// Callers have to check [ModuleArgs::silent](crate::args::ModuleArgs::silent)
+    /// before sending anything that isn't a question.
+    ///
+    /// Texts with null bytes in them return Err(PamResultCode::PAM_BUF_ERR),
+    /// and answers that aren't UTF-8 Err(PamResultCode::PAM_CONV_ERR).
+    pub fn converse(self, prompts: &[Prompt<'_>]) -> PamResult<Vec<Option<Answer>>> {
+        let conv = self
+            .get_item(PamItemType::PAM_CONV)
+            .map_err(|_| PamResultCode::PAM_CONV_ERR)?;
+        let conv = unsafe { &*(conv as *const RawConv) };
+        let function = conv.conv.ok_or(PamResultCode::PAM_CONV_ERR)?;
+
+        let texts = prompts
+            .iter()
+            .map(|prompt| CString::new(prompt.text))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| PamResultCode::PAM_BUF_ERR)?;
+        let messages: Vec<RawMessage> = prompts
+            .iter()
+            .zip(&texts)
+            .map(|(prompt, text)| RawMessage {
+                msg_style: prompt.style as c_int,
+                msg: text.as_ptr(),
+            })
+            .collect();
+        // Linux-PAM reads an array of pointers, Solaris a pointer to an array.
+        // Pointers into one array work for both.
+        let mut pointers: Vec<*const RawMessage> = messages.iter().map(|m| m as *const _).collect();
+
+        let mut responses: *mut RawResponse = ptr::null_mut();
+        // SAFETY: the application's conversation function gets as many
+        // message pointers as it is told, each pointing at a message whose
+        // text outlives the call, and the `appdata_ptr` it registered with.
+        let code = unsafe {
+            function(
+                pointers.len() as c_int,
+                pointers.as_mut_ptr(),
+                &mut responses,
+                conv.appdata_ptr,
+            )
+        };
+        let answers = unsafe { take_responses(responses, prompts.len()) };
+        PamResultCode::check(code)?;
+
+        prompts
+            .iter()
+            .zip(answers)
+            .map(|(prompt, answer)| match answer {
+                Some(answer) if prompt.is_question() => String::from_utf8(answer.to_vec())
+                    .map(|answer| Some(Zeroizing::new(answer)))
+                    .map_err(|why| {
+                        why.into_bytes().zeroize();
+                        PamResultCode::PAM_CONV_ERR
+                    }),
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Asks the user a question, showing what they type.
+    pub fn prompt_echo_on(self, prompt: &str) -> PamResult<Answer> {
+        self.ask(Prompt::echo_on(prompt))
+    }
+
+    /// Asks the user for a secret, such as a password, without showing what
+    /// they type.
+    pub fn prompt_echo_off(self, prompt: &str) -> PamResult<Answer> {
+        self.ask(Prompt::echo_off(prompt))
+    }
+
+    /// Sends a message to the user when doing a PAM conversation.
+    ///
+    /// Callers have to check [ModuleArgs::silent](crate::args::ModuleArgs::silent)
+    /// first, and most should go through [diagnose](crate::diagnose) instead.
+    pub fn info(self, msg: String) -> PamResult<()> {
+        self.converse(&[Prompt::info(&msg)]).map(drop)
+    }
+
+    /// Tells the user about a problem, which applications may show
+    /// differently from [Pam::info].
+    ///
+    /// Callers have to check [ModuleArgs::silent](crate::args::ModuleArgs::silent)
+    /// first.
+    pub fn error_msg(self, msg: String) -> PamResult<()> {
+        self.converse(&[Prompt::error(&msg)]).map(drop)
+    }
+
+    fn ask(self, prompt: Prompt<'_>) -> PamResult<Answer> {
+        self.converse(&[prompt])?
+            .pop()
+            .flatten()
+            .ok_or(PamResultCode::PAM_CONV_ERR)
+    }
+}
+
+/// Copies the answers out of the responses the conversation function
+/// allocated, then wipes and frees them.
+unsafe fn take_responses(
+    responses: *mut RawResponse,
+    count: usize,
+) -> Vec<Option<Zeroizing<Vec<u8>>>> {
+    if responses.is_null() {
+        return vec![None; count];
+    }
+
+    let answers = std::slice::from_raw_parts_mut(responses, count)
+        .iter_mut()
+        .map(|response| {
+            if response.resp.is_null() {
+                return None;
+            }
+            let len = libc::strlen(response.resp);
+            let bytes = std::slice::from_raw_parts_mut(response.resp as *mut u8, len);
+            let answer = Zeroizing::new(bytes.to_vec());
+            bytes.zeroize();
+            libc::free(response.resp as *mut c_void);
+            Some(answer)
+        })
+        .collect();
+    libc::free(responses as *mut c_void);
+    answers
+}
@@ -0,0 +1,184 @@
+//! The bits of cryptography shared by the audit log, signed requests, TOTP
+//! and approval IDs.
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// The SHA-256 of `data`.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// The HMAC-SHA256 of `data` with `key`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The HMAC-SHA1 of `data` with `key`, as TOTP uses.
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes any key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Checks `tag` against the HMAC-SHA256 of `data` with `key`, in constant
+/// time.
+pub fn verify_hmac_sha256(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key");
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+/// Lower case hex.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `bytes` random bytes from the kernel, in hex.
+pub fn random_hex(bytes: usize) -> Result<String, getrandom::Error> {
+    let mut buf = vec![0; bytes];
+    getrandom::getrandom(&mut buf)?;
+    Ok(hex(&buf))
+}
+
+/// Decodes hex of either case, or returns `None` if `hex` isn't hex.
+pub fn unhex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            let nibble = |c: u8| char::from(c).to_digit(16);
+            Some((nibble(pair[0])? << 4 | nibble(pair[1])?) as u8)
+        })
+        .collect()
+}
+
+/// Compares two byte strings in time that only depends on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Decodes RFC 4648 base32 of either case, ignoring spaces, dashes and
+/// padding, or returns `None` if `text` isn't base32.
+pub fn unbase32(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+
+    for c in text.bytes().filter(|c| !matches!(c, b' ' | b'-' | b'=')) {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = buffer << 5 | u64::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(hex(&[]), "");
+        assert_eq!(hex(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+        assert_eq!(unhex(b""), Some(Vec::new()));
+        assert_eq!(unhex(b"000fa5ff"), Some(vec![0x00, 0x0f, 0xa5, 0xff]));
+        assert_eq!(unhex(b"000FA5FF"), Some(vec![0x00, 0x0f, 0xa5, 0xff]));
+    }
+
+    #[test]
+    fn random_hex_differs_every_time() {
+        let a = random_hex(16).unwrap();
+        assert_eq!(a.len(), 32);
+        assert!(unhex(a.as_bytes()).is_some());
+        assert_ne!(a, random_hex(16).unwrap());
+    }
+
+    #[test]
+    fn unhex_rejects_what_isnt_hex() {
+        for hex in [
+            &b"0"[..],
+            b"abc",
+            b"+1",
+            b"-1",
+            b"+f",
+            b"0x",
+            b"zz",
+            b"a ",
+            b" a",
+            b"\xc3\xa9",
+        ] {
+            assert_eq!(unhex(hex), None, "{:?}", hex);
+        }
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // RFC 4231 test case 2 and RFC 2202 test case 2.
+        let tag = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&tag),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+
+        assert!(verify_hmac_sha256(
+            b"Jefe",
+            b"what do ya want for nothing?",
+            &tag
+        ));
+        assert!(!verify_hmac_sha256(
+            b"Jefe",
+            b"what do ya want for nothing!",
+            &tag
+        ));
+        assert!(!verify_hmac_sha256(
+            b"Jefe",
+            b"what do ya want for nothing?",
+            &tag[1..]
+        ));
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"123456", b"123456"));
+        assert!(!constant_time_eq(b"123456", b"123457"));
+        assert!(!constant_time_eq(b"123456", b"12345"));
+    }
+
+    #[test]
+    fn decodes_base32() {
+        assert_eq!(
+            unbase32("JBSWY3DPEHPK3PXP"),
+            Some(b"Hello!\xde\xad\xbe\xef".to_vec())
+        );
+        assert_eq!(
+            unbase32("jbsw y3dp-ehpk 3pxp===="),
+            Some(b"Hello!\xde\xad\xbe\xef".to_vec())
+        );
+        assert_eq!(unbase32(""), Some(Vec::new()));
+        assert_eq!(unbase32("JBSWY3DP1"), None);
+        assert_eq!(unbase32("JBSWY3DP8"), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! Running work in a detached process so logins never wait on the network.
+
+use std::{io, ptr, time::Duration};
+
+/// How long a detached process may run unless the configuration says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs `f` in a double-forked grandchild process and returns as soon as the
+/// intermediate child has exited, which it does straight away.
+///
+/// The grandchild is reparented to init, so nothing has to reap it. It gets
+/// its own session, stdin/stdout/stderr pointed at /dev/null and every other
+/// file descriptor closed, so it can't hold the user's connection open. It is
+/// killed with `SIGALRM` if it is still running after `timeout`.
+///
+/// The grandchild is a copy of the calling process, so `f` must not touch
+/// anything that another thread could have been holding a lock on at the time
+/// of the fork. sshd and login are single threaded while running PAM modules.
+pub fn detached(timeout: Duration, f: impl FnOnce()) -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            // Intermediate child: start a new session so the grandchild doesn't
+            // get the terminal's signals, fork again and leave.
+            unsafe {
+                libc::setsid();
+                if libc::fork() != 0 {
+                    libc::_exit(0);
+                }
+            }
+
+            become_daemon(timeout);
+            f();
+            unsafe { libc::_exit(0) }
+        }
+        pid => {
+            // The host application may already reap children in its SIGCHLD
+            // handler, so failing to wait here isn't an error.
+            unsafe { libc::waitpid(pid, ptr::null_mut(), 0) };
+            Ok(())
+        }
+    }
+}
+
+fn become_daemon(timeout: Duration) {
+    unsafe {
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null >= 0 {
+            for fd in 0..3 {
+                libc::dup2(null, fd);
+            }
+            if null > 2 {
+                libc::close(null);
+            }
+        }
+
+        if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) != 0 {
+            for fd in 3..1024 {
+                libc::close(fd);
+            }
+        }
+
+        // Signal handlers and the signal mask are inherited from the host
+        // application, which may well be ignoring SIGALRM.
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigprocmask(libc::SIG_SETMASK, &set, ptr::null_mut());
+        libc::signal(libc::SIGALRM, libc::SIG_DFL);
+        libc::alarm(timeout.as_secs().clamp(1, libc::c_uint::MAX as u64) as libc::c_uint);
+    }
+}
@@ -0,0 +1,188 @@
+//! Collapsing bursts of similar events into one summary.
+//!
+//! The first event for a key is sent straight away and opens a window. Any
+//! more events for the same key during the window are only counted. Once the
+//! window is over, the count is sent as a single summary event.
+
+use crate::event::{self, Event, EventKind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How many distinct users a summary lists before it stops adding more.
+const MAX_USERS: usize = 10;
+
+/// Every open window, by key. This is what gets stored with [crate::state].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Digests {
+    windows: BTreeMap<String, Window>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Window {
+    /// The event that opened the window, which summaries are based on.
+    first: Event,
+    /// When the window opened, in seconds since the Unix epoch.
+    start: u64,
+    /// How many events were counted instead of being sent.
+    suppressed: u32,
+    /// The users of the counted events, up to [MAX_USERS].
+    users: Vec<String>,
+}
+
+impl Digests {
+    /// Records `event` under `key` and returns true if it should be sent now,
+    /// which is the case if it opened a new window.
+    ///
+    /// Call [Digests::take_expired] first, so the summary of an old window for
+    /// the same key isn't lost.
+    pub fn record(&mut self, key: &str, event: &Event, window: u64) -> bool {
+        if let Some(open) = self.windows.get_mut(key) {
+            if event.time < open.start.saturating_add(window) {
+                open.suppressed += 1;
+                if open.users.len() < MAX_USERS && !open.users.contains(&event.user) {
+                    open.users.push(event.user.clone());
+                }
+                return false;
+            }
+        }
+
+        self.windows.insert(
+            key.to_string(),
+            Window {
+                first: event.clone(),
+                start: event.time,
+                suppressed: 0,
+                users: Vec::new(),
+            },
+        );
+        true
+    }
+
+    /// Closes every window that ended before `now` and returns summaries of
+    /// the ones that counted any events, as events of kind `kind`.
+    pub fn take_expired(&mut self, now: u64, window: u64, kind: EventKind) -> Vec<Event> {
+        let expired: Vec<String> = self
+            .windows
+            .iter()
+            .filter(|(_, open)| now >= open.start.saturating_add(window))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|key| self.windows.remove(&key))
+            .filter(|open| open.suppressed > 0)
+            .map(|open| Event {
+                id: event::new_id(),
+                kind,
+                user: open.users.join(", "),
+                time: now,
+                count: Some(open.suppressed),
+                duration: Some(window),
+                session: None,
+                ..open.first
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 600;
+
+    fn event(user: &str, time: u64) -> Event {
+        Event {
+            id: event::new_id(),
+            kind: EventKind::AuthFailure,
+            user: user.to_string(),
+            rhost: "192.0.2.1".to_string(),
+            ruser: String::new(),
+            service: "sshd".to_string(),
+            tty: String::new(),
+            hostname: "example".to_string(),
+            time,
+            session: None,
+            duration: None,
+            count: None,
+            lock: None,
+        }
+    }
+
+    #[test]
+    fn sends_the_first_event_and_counts_the_rest() {
+        let mut digests = Digests::default();
+        assert!(digests.record("rhost", &event("alice", 1000), WINDOW));
+        assert!(!digests.record("rhost", &event("bob", 1001), WINDOW));
+        assert!(!digests.record("rhost", &event("alice", 1599), WINDOW));
+        // Other keys have windows of their own.
+        assert!(digests.record("other", &event("carol", 1002), WINDOW));
+
+        assert!(digests
+            .take_expired(1599, WINDOW, EventKind::AuthFailureSummary)
+            .is_empty());
+        let summaries = digests.take_expired(1600, WINDOW, EventKind::AuthFailureSummary);
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.kind, EventKind::AuthFailureSummary);
+        assert_eq!(summary.user, "bob, alice");
+        assert_eq!(summary.rhost, "192.0.2.1");
+        assert_eq!(summary.time, 1600);
+        assert_eq!(summary.count, Some(2));
+        assert_eq!(summary.duration, Some(WINDOW));
+    }
+
+    #[test]
+    fn drops_windows_without_suppressed_events() {
+        let mut digests = Digests::default();
+        assert!(digests.record("rhost", &event("alice", 1000), WINDOW));
+        assert!(digests
+            .take_expired(1600, WINDOW, EventKind::AuthFailureSummary)
+            .is_empty());
+        // The window is gone, so the next event is sent again.
+        assert!(digests.record("rhost", &event("alice", 1601), WINDOW));
+    }
+
+    #[test]
+    fn opens_a_new_window_once_the_old_one_ended() {
+        let mut digests = Digests::default();
+        assert!(digests.record("rhost", &event("alice", 1000), WINDOW));
+        assert!(!digests.record("rhost", &event("alice", 1001), WINDOW));
+        // Recorded without taking the expired window first, which replaces it.
+        assert!(digests.record("rhost", &event("alice", 1600), WINDOW));
+        assert!(digests
+            .take_expired(1600, WINDOW, EventKind::LoginSummary)
+            .is_empty());
+    }
+
+    #[test]
+    fn lists_each_user_once_up_to_the_limit() {
+        let mut digests = Digests::default();
+        assert!(digests.record("rhost", &event("first", 1000), WINDOW));
+        for i in 0..20 {
+            let user = format!("user{}", i % 15);
+            assert!(!digests.record("rhost", &event(&user, 1001), WINDOW));
+        }
+        let summaries = digests.take_expired(2000, WINDOW, EventKind::LoginSummary);
+        assert_eq!(summaries[0].count, Some(20));
+        let users: Vec<&str> = summaries[0].user.split(", ").collect();
+        assert_eq!(users.len(), MAX_USERS);
+        assert_eq!(users[0], "user0");
+        assert_eq!(users[MAX_USERS - 1], "user9");
+    }
+
+    #[test]
+    fn window_ends_saturate() {
+        let mut digests = Digests::default();
+        assert!(digests.record("rhost", &event("alice", u64::MAX - 10), WINDOW));
+        assert!(!digests.record("rhost", &event("alice", u64::MAX - 1), WINDOW));
+        assert!(digests
+            .take_expired(u64::MAX - 1, WINDOW, EventKind::LoginSummary)
+            .is_empty());
+        assert_eq!(
+            digests.take_expired(u64::MAX, WINDOW, EventKind::LoginSummary)[0].count,
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Events the module tells notifiers about.
+
+use crate::{
+    session::{self, Session},
+    Pam,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Login,
+    Logout,
+    /// Authentication failed, see the `auth_fail` module argument.
+    AuthFailure,
+    /// More authentication failures happened than were worth a message each.
+    AuthFailureSummary,
+    /// Logins that were held back by the `[rate_limit]` section.
+    LoginSummary,
+    /// A login waiting to be approved, see the `approve` module argument.
+    ApprovalRequest,
+    /// A user or remote host was locked out by the `[lockout]` section.
+    Lockout,
+}
+
+impl EventKind {
+    /// Every kind of event.
+    pub const ALL: &'static [EventKind] = &[
+        EventKind::Login,
+        EventKind::Logout,
+        EventKind::AuthFailure,
+        EventKind::AuthFailureSummary,
+        EventKind::LoginSummary,
+        EventKind::ApprovalRequest,
+        EventKind::Lockout,
+    ];
+
+    /// The name of the kind of event, as used in JSON and the configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Login => "login",
+            EventKind::Logout => "logout",
+            EventKind::AuthFailure => "auth_failure",
+            EventKind::AuthFailureSummary => "auth_failure_summary",
+            EventKind::LoginSummary => "login_summary",
+            EventKind::ApprovalRequest => "approval_request",
+            EventKind::Lockout => "lockout",
+        }
+    }
+
+    /// Looks up a kind of event by its [EventKind::name].
+    pub fn from_name(name: &str) -> Option<EventKind> {
+        EventKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+/// Something that happened on this machine that notifiers should hear about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Identifies the event in logs, see [new_id]. Approval requests get a
+    /// random one instead, see [crate::approve].
+    #[serde(default)]
+    pub id: String,
+    pub kind: EventKind,
+    pub user: String,
+    /// The remote host, empty for local logins.
+    pub rhost: String,
+    /// The user asking to become `user`, for services like `sudo` and `su`.
+    /// Empty if the service doesn't set it.
+    #[serde(default)]
+    pub ruser: String,
+    /// The PAM service, such as `sshd` or `sudo`.
+    #[serde(default)]
+    pub service: String,
+    /// The terminal the session is on, such as `pts/3`. Empty if unknown.
+    #[serde(default)]
+    pub tty: String,
+    /// The name of the machine the module is running on.
+    pub hostname: String,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// Identifies the session, so a logout can be matched to its login.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// How long the session lasted in seconds for logouts, how long a
+    /// summary covers, or how long a lockout lasts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// How many events a summary stands for, or how many failures led to a
+    /// lockout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+    /// What a lockout locked, like `user:alice` or `rhost:192.0.2.1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<String>,
+}
+
+impl Event {
+    /// Creates an event of `kind` for the user currently authenticating.
+    ///
+    /// Logouts get their duration from `session`.
+    pub fn new(pamh: Pam<'_>, kind: EventKind, session: Option<&Session>) -> Event {
+        Event {
+            id: new_id(),
+            kind,
+            user: pamh.user().map_or_else(|| "<unknown>".into(), String::from),
+            rhost: pamh.rhost().map(String::from).unwrap_or_default(),
+            ruser: pamh.ruser().map(String::from).unwrap_or_default(),
+            service: pamh
+                .service()
+                .map_or_else(|| "<unknown>".into(), String::from),
+            tty: pamh.tty().map(String::from).unwrap_or_default(),
+            hostname: hostname(),
+            time: now(),
+            session: session.map(|s| s.id.clone()),
+            duration: match kind {
+                EventKind::Logout => session.map(session::duration),
+                _ => None,
+            },
+            count: None,
+            lock: None,
+        }
+    }
+
+    /// Describes who is logging in, such as `alice`, or `bob → root` for
+    /// services like `sudo`.
+    pub fn who(&self) -> String {
+        if !self.ruser.is_empty() && self.ruser != self.user {
+            format!("{} → {}", self.ruser, self.user)
+        } else {
+            self.user.clone()
+        }
+    }
+
+    /// Describes where the login is coming from, such as ` from 192.0.2.1 via
+    /// sshd on pts/3`. Parts that aren't known are left out.
+    pub fn whence(&self) -> String {
+        let mut whence = String::new();
+        if !self.rhost.is_empty() {
+            whence += &format!(" from {}", self.rhost);
+        }
+        whence += &format!(" via {}", self.service);
+        if !self.tty.is_empty() {
+            whence += &format!(" on {}", self.tty);
+        }
+        whence
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            EventKind::Login => write!(f, "{} logged in{}", self.who(), self.whence()),
+            EventKind::Logout => {
+                write!(f, "{} logged out{}", self.who(), self.whence())?;
+                match self.duration {
+                    Some(duration) => write!(f, " after {}", format_duration(duration)),
+                    None => Ok(()),
+                }
+            }
+            EventKind::AuthFailure => {
+                write!(f, "failed login for {}{}", self.who(), self.whence())
+            }
+            EventKind::AuthFailureSummary => write!(
+                f,
+                "{} more failed logins for {}{} in the last {}",
+                self.count.unwrap_or(0),
+                self.who(),
+                self.whence(),
+                format_duration(self.duration.unwrap_or(0))
+            ),
+            EventKind::LoginSummary => write!(
+                f,
+                "{} more logins for {}{} in the last {}",
+                self.count.unwrap_or(0),
+                self.who(),
+                self.whence(),
+                format_duration(self.duration.unwrap_or(0))
+            ),
+            EventKind::ApprovalRequest => write!(
+                f,
+                "{} wants to log in{}, approval {}",
+                self.who(),
+                self.whence(),
+                self.id
+            ),
+            EventKind::Lockout => write!(
+                f,
+                "{} failed logins for {}{}, locked out {} for {}",
+                self.count.unwrap_or(0),
+                self.who(),
+                self.whence(),
+                self.lock.as_deref().unwrap_or_default(),
+                format_duration(self.duration.unwrap_or(0))
+            ),
+        }
+    }
+}
+
+/// The current time in seconds since the Unix epoch, or 0 if the clock is
+/// set before it.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Makes up an ID for an event, like `1657129020-4711-1`: the time, the
+/// process ID and a counter, which is unique enough to find an event's
+/// deliveries in the logs.
+pub fn new_id() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let time = now();
+    format!(
+        "{}-{}-{}",
+        time,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) + 1
+    )
+}
+
+/// Formats a number of seconds like `1d 2h 3m 4s`, leaving out leading units
+/// that are zero.
+pub fn format_duration(secs: u64) -> String {
+    let units = [
+        (secs / 86400, "d"),
+        ((secs / 3600) % 24, "h"),
+        ((secs / 60) % 60, "m"),
+        (secs % 60, "s"),
+    ];
+
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(n, _)| *n == 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if parts.is_empty() {
+        return "0s".into();
+    }
+    parts.join(" ")
+}
+
+/// Gets the name of this machine, or `<unknown>` if it can't be found.
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let r = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if r != 0 {
+        return "<unknown>".into();
+    }
+
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
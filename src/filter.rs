@@ -0,0 +1,322 @@
+//! Rules deciding which events are worth a notification.
+//!
+//! Rules are given in the `[filter]` section as `include_*` and `exclude_*`
+//! lists, each of which can be repeated:
+//!
+//! ```text
+//! [filter]
+//! include_service = sshd, sudo
+//! exclude_user = backup
+//! exclude_rhost = 10.0.0.0/8, fd00::/8
+//! ```
+//!
+//! An event is notified about if, for every kind of rule, it matches one of the
+//! `include_*` entries (or there are none) and none of the `exclude_*` entries.
+
+use crate::{event::Event, passwd};
+use std::net::IpAddr;
+
+/// The `[filter]` section.
+#[derive(Debug, Default)]
+pub struct Filter {
+    users: Rules<String>,
+    groups: Rules<String>,
+    services: Rules<String>,
+    ttys: Rules<String>,
+    rhosts: Rules<HostPattern>,
+}
+
+#[derive(Debug)]
+struct Rules<T> {
+    include: Vec<T>,
+    exclude: Vec<T>,
+}
+
+impl<T> Default for Rules<T> {
+    fn default() -> Self {
+        Rules {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl<T> Rules<T> {
+    fn allows(&self, matches: impl Fn(&T) -> bool) -> bool {
+        (self.include.is_empty() || self.include.iter().any(&matches))
+            && !self.exclude.iter().any(&matches)
+    }
+}
+
+/// An `include_rhost` or `exclude_rhost` entry, also used by
+/// [crate::access].
+#[derive(Debug)]
+pub enum HostPattern {
+    /// An address range such as `10.0.0.0/8` or `2001:db8::/32`. A single
+    /// address is a range of one.
+    Network(IpAddr, u8),
+    /// A glob matched against host names, for when sshd is resolving them.
+    Name(String),
+}
+
+impl HostPattern {
+    pub fn parse(value: &str) -> Result<HostPattern, String> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+
+        let addr: IpAddr = match addr.parse() {
+            Ok(addr) => addr,
+            Err(_) if prefix.is_none() => return Ok(HostPattern::Name(value.to_string())),
+            Err(_) => return Err(format!("invalid network {:?}", value)),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&p| p <= max)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", value))?,
+            None => max,
+        };
+
+        Ok(HostPattern::Network(addr, prefix))
+    }
+
+    pub fn matches(&self, rhost: &str) -> bool {
+        match self {
+            HostPattern::Network(network, prefix) => rhost
+                .parse::<IpAddr>()
+                .map(|addr| in_network(addr.to_canonical(), *network, *prefix))
+                .unwrap_or(false),
+            HostPattern::Name(pattern) => glob(pattern, rhost),
+        }
+    }
+}
+
+/// Returns true if the first `prefix` bits of `addr` and `network` are the
+/// same. Addresses of different families never match.
+fn in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (addr, network, bits) = match (addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+        _ => return false,
+    };
+    if prefix == 0 {
+        return true;
+    }
+
+    let shift = bits - prefix as u32;
+    addr >> shift == network >> shift
+}
+
+/// Matches `s` against a glob where `*` matches any run of characters and `?`
+/// matches any one character.
+pub fn glob(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut p, mut i) = (0, 0);
+    let mut backtrack = None;
+
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, i));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    i = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Filter {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let (include, kind) = match key.split_once('_') {
+            Some(("include", kind)) => (true, kind),
+            Some(("exclude", kind)) => (false, kind),
+            _ => return Err(format!("unknown key {:?}", key)),
+        };
+
+        let items = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty());
+        fn add<T>(rules: &mut Rules<T>, include: bool, items: impl Iterator<Item = T>) {
+            match include {
+                true => rules.include.extend(items),
+                false => rules.exclude.extend(items),
+            }
+        }
+
+        match kind {
+            "user" => add(&mut self.users, include, items.map(str::to_string)),
+            "group" => add(&mut self.groups, include, items.map(str::to_string)),
+            "service" => add(&mut self.services, include, items.map(str::to_string)),
+            "tty" => add(&mut self.ttys, include, items.map(str::to_string)),
+            "rhost" => {
+                let patterns = items
+                    .map(HostPattern::parse)
+                    .collect::<Result<Vec<_>, _>>()?;
+                add(&mut self.rhosts, include, patterns.into_iter())
+            }
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the rules allow notifying about `event`.
+    ///
+    /// Group rules are only looked up if there are any, since that may mean
+    /// asking a directory server.
+    pub fn allows(&self, event: &Event) -> bool {
+        self.users.allows(|user| *user == event.user)
+            && self.services.allows(|service| *service == event.service)
+            && self.ttys.allows(|pattern| glob(pattern, &event.tty))
+            && self.rhosts.allows(|pattern| pattern.matches(&event.rhost))
+            && (self.groups.include.is_empty() && self.groups.exclude.is_empty() || {
+                let groups = passwd::user_groups(&event.user).unwrap_or_default();
+                self.groups.allows(|group| {
+                    passwd::group_id(group).is_some_and(|gid| groups.contains(&gid))
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, rhost: &str) -> bool {
+        HostPattern::parse(pattern).unwrap().matches(rhost)
+    }
+
+    #[test]
+    fn parses_networks_and_names() {
+        assert!(matches!(
+            HostPattern::parse("10.0.0.0/8"),
+            Ok(HostPattern::Network(IpAddr::V4(_), 8))
+        ));
+        assert!(matches!(
+            HostPattern::parse("192.0.2.1"),
+            Ok(HostPattern::Network(IpAddr::V4(_), 32))
+        ));
+        assert!(matches!(
+            HostPattern::parse("2001:db8::1"),
+            Ok(HostPattern::Network(IpAddr::V6(_), 128))
+        ));
+        assert!(matches!(
+            HostPattern::parse("::/0"),
+            Ok(HostPattern::Network(IpAddr::V6(_), 0))
+        ));
+        assert!(matches!(
+            HostPattern::parse("*.example.org"),
+            Ok(HostPattern::Name(name)) if name == "*.example.org"
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_prefixes() {
+        for value in [
+            "10.0.0.0/33",
+            "2001:db8::/129",
+            "10.0.0.0/256",
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+            "10.0.0.0/eight",
+            "10.0.0/8",
+            "example.org/8",
+        ] {
+            assert!(HostPattern::parse(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn matches_prefixes() {
+        assert!(matches("0.0.0.0/0", "203.0.113.7"));
+        assert!(matches("::/0", "2001:db8::1"));
+        assert!(!matches("0.0.0.0/0", "2001:db8::1"));
+        assert!(!matches("::/0", "203.0.113.7"));
+
+        assert!(matches("192.0.2.1/32", "192.0.2.1"));
+        assert!(!matches("192.0.2.1/32", "192.0.2.2"));
+        assert!(matches("2001:db8::1/128", "2001:db8::1"));
+        assert!(!matches("2001:db8::1/128", "2001:db8::2"));
+
+        assert!(matches("10.0.0.0/8", "10.255.0.1"));
+        assert!(!matches("10.0.0.0/8", "11.0.0.1"));
+        // Host bits in the network are ignored.
+        assert!(matches("10.1.2.3/8", "10.9.9.9"));
+        assert!(matches("192.0.2.128/25", "192.0.2.200"));
+        assert!(!matches("192.0.2.128/25", "192.0.2.127"));
+        assert!(matches("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!matches("2001:db8::/32", "2001:db9::1"));
+    }
+
+    #[test]
+    fn matches_mapped_ipv4_addresses_against_ipv4_networks() {
+        assert!(matches("192.0.2.0/24", "::ffff:192.0.2.7"));
+        assert!(matches("192.0.2.7", "::ffff:192.0.2.7"));
+        assert!(!matches("192.0.2.0/24", "::ffff:198.51.100.7"));
+        assert!(matches("0.0.0.0/0", "::ffff:198.51.100.7"));
+    }
+
+    #[test]
+    fn networks_dont_match_names() {
+        assert!(!matches("10.0.0.0/8", "host.example.org"));
+        assert!(!matches("10.0.0.0/8", ""));
+    }
+
+    #[test]
+    fn in_network_never_mixes_families() {
+        let v4: IpAddr = "0.0.0.0".parse().unwrap();
+        let v6: IpAddr = "::".parse().unwrap();
+        assert!(!in_network(v4, v6, 0));
+        assert!(!in_network(v6, v4, 0));
+        assert!(in_network(v4, v4, 32));
+        assert!(in_network(v6, v6, 128));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("", ""));
+        assert!(!glob("", "a"));
+        assert!(glob("*", ""));
+        assert!(glob("*", "anything"));
+        assert!(glob("**", "a"));
+        assert!(!glob("?", ""));
+        assert!(glob("?", "a"));
+        assert!(glob("?", "é"));
+        assert!(!glob("?", "ab"));
+        assert!(glob("pts/*", "pts/3"));
+        assert!(!glob("pts/*", "tty1"));
+        assert!(glob("*.example.org", "host.example.org"));
+        assert!(!glob("*.example.org", "example.org"));
+        assert!(glob("a*b*c", "aXbYbZc"));
+        assert!(!glob("a*b*c", "aXbYbZ"));
+        assert!(glob("*a?", "bananas"));
+        assert!(!glob("*a?", "banana"));
+        assert!(glob("h?st*", "host1"));
+        assert!(!glob("HOST", "host"));
+    }
+
+    #[test]
+    fn matches_names_with_globs() {
+        assert!(matches("*.example.org", "ssh.example.org"));
+        assert!(!matches("*.example.org", "192.0.2.1"));
+    }
+}
@@ -0,0 +1,295 @@
+//! The HTTP client every notifier sends its requests with.
+
+use crate::{
+    config::HttpConfig,
+    crypto::{hex, hmac_sha256},
+    event::now,
+};
+use curl::easy::{Easy, Form, List};
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+
+/// The body of an outgoing request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Body {
+    /// A `multipart/form-data` body made of `(name, contents)` parts.
+    Form(Vec<(String, String)>),
+    /// An `application/json` body.
+    Json(serde_json::Value),
+    /// A `text/plain` body.
+    Text(String),
+}
+
+/// An outgoing HTTP request.
+///
+/// Requests can be serialized so that failed ones can be spooled to disk and
+/// sent again later exactly as they were.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    /// Send the request with `PUT` instead of `POST`.
+    pub put: bool,
+    pub url: String,
+    /// Extra headers in `Name: value` form.
+    pub headers: Vec<String>,
+    pub body: Body,
+    /// How to sign the request, see [Signing].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<Signing>,
+}
+
+/// Signs requests so the receiver can tell they came from us and aren't
+/// replayed.
+///
+/// Every time the request is sent, the current Unix time goes in the
+/// `HEADER-Timestamp` header and `sha256=` followed by the hex HMAC-SHA256 of
+/// `TIMESTAMP.BODY` with the secret goes in `HEADER`. Receivers should reject
+/// requests whose timestamp is more than a few minutes off. Spooled requests
+/// are signed again when they are retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signing {
+    pub secret: String,
+    pub header: String,
+}
+
+impl Signing {
+    /// The hex HMAC-SHA256 of `TIMESTAMP.BODY`.
+    fn signature(&self, timestamp: &str, body: &[u8]) -> String {
+        let mut signed = format!("{}.", timestamp).into_bytes();
+        signed.extend_from_slice(body);
+        hex(&hmac_sha256(self.secret.as_bytes(), &signed))
+    }
+}
+
+impl Request {
+    /// Creates a `POST` request to `url`.
+    pub fn post(url: impl Into<String>, body: Body) -> Request {
+        Request {
+            put: false,
+            url: url.into(),
+            headers: Vec::new(),
+            body,
+            signing: None,
+        }
+    }
+
+    /// Creates a `PUT` request to `url`.
+    pub fn put(url: impl Into<String>, body: Body) -> Request {
+        Request {
+            put: true,
+            ..Request::post(url, body)
+        }
+    }
+
+    /// Adds a `Name: value` header to the request.
+    pub fn header(mut self, name: &str, value: &str) -> Request {
+        self.headers.push(format!("{}: {}", name, value));
+        self
+    }
+
+    /// Signs the request with `signing` whenever it is sent.
+    pub fn signed(mut self, signing: Option<Signing>) -> Request {
+        self.signing = signing;
+        self
+    }
+}
+
+/// Everything that can go wrong when sending a request.
+#[derive(Debug)]
+pub enum HttpError {
+    Curl(curl::Error),
+    Form(curl::FormError),
+    /// The server answered with a non-2xx status code.
+    Status(u32),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Curl(why) => write!(f, "{}", why),
+            HttpError::Form(why) => write!(f, "{}", why),
+            HttpError::Status(code) => write!(f, "got status code {}", code),
+        }
+    }
+}
+
+impl From<curl::Error> for HttpError {
+    fn from(why: curl::Error) -> Self {
+        HttpError::Curl(why)
+    }
+}
+
+impl From<curl::FormError> for HttpError {
+    fn from(why: curl::FormError) -> Self {
+        HttpError::Form(why)
+    }
+}
+
+/// Sends a request and returns the response status code, which is always 2xx.
+pub fn send(config: &HttpConfig, request: &Request) -> Result<u32, HttpError> {
+    let mut easy = Easy::new();
+    easy.url(&request.url)?;
+    configure(&mut easy, config)?;
+
+    let mut headers = List::new();
+    headers.append("User-Agent: pam_rc2022")?;
+    for header in &request.headers {
+        headers.append(header)?;
+    }
+
+    let fields = match &request.body {
+        Body::Form(parts) => {
+            let mut form = Form::new();
+            for (name, contents) in parts {
+                form.part(name).contents(contents.as_bytes()).add()?;
+            }
+            easy.httppost(form)?;
+            None
+        }
+        Body::Json(value) => {
+            headers.append("Content-Type: application/json")?;
+            Some(value.to_string().into_bytes())
+        }
+        Body::Text(text) => {
+            headers.append("Content-Type: text/plain; charset=utf-8")?;
+            Some(text.clone().into_bytes())
+        }
+    };
+
+    if let Some(signing) = &request.signing {
+        // curl makes up the multipart boundary, so form bodies can't be signed.
+        // Configurations that would need that are refused when loaded.
+        let timestamp = now().to_string();
+        let signature = signing.signature(&timestamp, fields.as_deref().unwrap_or_default());
+
+        headers.append(&format!("{}-Timestamp: {}", signing.header, timestamp))?;
+        headers.append(&format!("{}: sha256={}", signing.header, signature))?;
+    }
+    if let Some(fields) = fields {
+        easy.post_fields_copy(&fields)?;
+    }
+
+    if request.put {
+        easy.custom_request("PUT")?;
+    }
+    easy.http_headers(headers)?;
+    easy.perform()?;
+
+    let response_code = easy.response_code()?;
+    if response_code.div_euclid(100) != 2 {
+        return Err(HttpError::Status(response_code));
+    }
+
+    Ok(response_code)
+}
+
+/// Fetches `url` with extra `Name: value` `headers`, taking at most
+/// `timeout`, and returns the response status code, which is always 2xx, and
+/// body.
+pub fn get(
+    config: &HttpConfig,
+    url: &str,
+    headers: &[String],
+    timeout: Duration,
+) -> Result<(u32, Vec<u8>), HttpError> {
+    let mut easy = Easy::new();
+    easy.url(url)?;
+    configure(&mut easy, config)?;
+    easy.timeout(timeout)?;
+
+    let mut list = List::new();
+    list.append("User-Agent: pam_rc2022")?;
+    for header in headers {
+        list.append(header)?;
+    }
+    easy.http_headers(list)?;
+
+    let mut body = Vec::new();
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+
+    let response_code = easy.response_code()?;
+    if response_code.div_euclid(100) != 2 {
+        return Err(HttpError::Status(response_code));
+    }
+
+    Ok((response_code, body))
+}
+
+/// Applies the `[http]` section of the configuration to a curl handle.
+fn configure(easy: &mut Easy, config: &HttpConfig) -> Result<(), curl::Error> {
+    easy.connect_timeout(config.connect_timeout)?;
+    easy.timeout(config.timeout)?;
+    // Timeouts are implemented with SIGALRM otherwise, which isn't ours to use.
+    easy.signal(false)?;
+
+    if let Some(proxy) = &config.proxy {
+        easy.proxy(proxy)?;
+    }
+    if let Some(ca_file) = &config.ca_file {
+        easy.cainfo(ca_file)?;
+    }
+    if let Some(key) = &config.pinned_public_key {
+        easy.pinned_public_key(key)?;
+    }
+    if let Some(cert) = &config.client_cert {
+        easy.ssl_cert(cert)?;
+        easy.ssl_key(config.client_key.as_ref().unwrap_or(cert))?;
+    }
+
+    Ok(())
+}
+
+/// Percent-encodes `s` so it can be used as a single URL path segment.
+pub fn encode_path_segment(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                result.push(b as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        let signing = Signing {
+            secret: "hunter2".to_string(),
+            header: "X-Signature".to_string(),
+        };
+        let body = serde_json::json!({ "text": "alice logged in" }).to_string();
+        assert_eq!(body, r#"{"text":"alice logged in"}"#);
+        assert_eq!(
+            signing.signature("1657129020", body.as_bytes()),
+            "9e207b750ae4c7a4f977a848ad3b63cd9437b50c4c3a5792fcafc35278573478"
+        );
+        assert_eq!(
+            signing.signature("1657129020", b""),
+            "1f0c7d0f92db2e9e3f1341000631d929b66d433402a129e18bb68cf068bfd087"
+        );
+        assert_ne!(
+            signing.signature("1657129021", body.as_bytes()),
+            signing.signature("1657129020", body.as_bytes())
+        );
+    }
+
+    #[test]
+    fn encodes_path_segments() {
+        assert_eq!(encode_path_segment("abc-XYZ_0.9~"), "abc-XYZ_0.9~");
+        assert_eq!(encode_path_segment("a/b c"), "a%2Fb%20c");
+        assert_eq!(encode_path_segment("é"), "%C3%A9");
+    }
+}
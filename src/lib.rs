@@ -1,22 +1,105 @@
-use curl::easy::{Easy, Form, List};
 use std::ffi::{CStr, CString};
 use std::{
+    borrow::Cow,
+    fmt,
+    marker::{PhantomData, PhantomPinned},
     os::raw::{c_char, c_int, c_uint, c_void},
+    path::Path,
     ptr,
 };
 
-pub type PamHandle = *const c_uint;
+pub mod access;
+pub mod approve;
+pub mod args;
+pub mod audit;
+pub mod config;
+pub mod conv;
+pub mod crypto;
+pub mod detach;
+pub mod digest;
+pub mod event;
+pub mod filter;
+pub mod http;
+pub mod lockout;
+pub mod log;
+pub mod notifier;
+pub mod passwd;
+pub mod session;
+pub mod spool;
+pub mod state;
+pub mod template;
+pub mod totp;
+
+use args::ModuleArgs;
+use audit::{Deliveries, Delivery, Outcome};
+use config::{Config, Diagnostics};
+use digest::Digests;
+use event::{now, Event, EventKind};
+use log::{Level, Log, Logger, Message};
+use notifier::{Discord, Notifier};
+
+/// The `pam_handle_t` behind a PAM transaction. It is opaque and only ever
+/// seen behind a [Pam].
+#[repr(C)]
+pub struct PamHandle {
+    _data: [u8; 0],
+    _marker: PhantomData<(*mut u8, PhantomPinned)>,
+}
+
+/// The PAM transaction a `pam_sm_*` callback was called for.
+///
+/// Everything the module asks of PAM goes through this. It only lives as long
+/// as the callback, and so do the items borrowed from it.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Pam<'a>(&'a PamHandle);
+
 pub type PamFlags = c_uint;
 pub type PamResult<T> = Result<T, PamResultCode>;
 
 pub const PAM_SILENT: PamFlags = 0x8000;
 
-/// All of the PAM result codes that can be returned by modules. See [man 3 pam](https://linux.die.net/man/3/pam)
-/// for more information about what these result codes mean.
-#[allow(non_camel_case_types, dead_code)]
-#[derive(Debug)]
-#[repr(C)]
-pub enum PamResultCode {
+macro_rules! result_codes {
+    ($($name:ident = $code:literal,)*) => {
+        /// All of the PAM result codes that can be returned by modules. See [man 3 pam](https://linux.die.net/man/3/pam)
+        /// for more information about what these result codes mean.
+        ///
+        /// Codes are exchanged with libpam as plain `c_int`s, since a PAM
+        /// implementation may well return one that isn't listed here. Those
+        /// become [PamResultCode::Unknown].
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum PamResultCode {
+            $($name,)*
+            /// A code this module doesn't know about.
+            Unknown(c_int),
+        }
+
+        impl TryFrom<c_int> for PamResultCode {
+            type Error = c_int;
+
+            /// Converts a code from libpam, failing with the code itself if
+            /// it isn't one of the known ones.
+            fn try_from(code: c_int) -> Result<Self, c_int> {
+                match code {
+                    $($code => Ok(PamResultCode::$name),)*
+                    _ => Err(code),
+                }
+            }
+        }
+
+        impl From<PamResultCode> for c_int {
+            fn from(code: PamResultCode) -> c_int {
+                match code {
+                    $(PamResultCode::$name => $code,)*
+                    PamResultCode::Unknown(code) => code,
+                }
+            }
+        }
+    };
+}
+
+result_codes! {
     PAM_SUCCESS = 0,
     PAM_OPEN_ERR = 1,
     PAM_SYMBOL_ERR = 2,
@@ -51,9 +134,38 @@ pub enum PamResultCode {
     PAM_INCOMPLETE = 31,
 }
 
-/// PAM message styles.
-#[allow(non_camel_case_types, dead_code)]
-#[derive(Debug)]
+impl PamResultCode {
+    /// Converts a code from libpam, keeping unknown ones as
+    /// [PamResultCode::Unknown].
+    pub fn from_raw(code: c_int) -> PamResultCode {
+        PamResultCode::try_from(code).unwrap_or(PamResultCode::Unknown(code))
+    }
+
+    /// Turns a code from libpam into a [PamResult].
+    fn check(code: c_int) -> PamResult<()> {
+        match PamResultCode::from_raw(code) {
+            PamResultCode::PAM_SUCCESS => Ok(()),
+            why => Err(why),
+        }
+    }
+}
+
+impl fmt::Display for PamResultCode {
+    /// Describes the code the way libpam does, such as "Authentication
+    /// failure".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Neither Linux-PAM nor OpenPAM look at the handle.
+        let msg = unsafe { sys::pam_strerror(ptr::null(), (*self).into()) };
+        if msg.is_null() {
+            return write!(f, "unknown PAM error {}", c_int::from(*self));
+        }
+        f.write_str(&unsafe { CStr::from_ptr(msg) }.to_string_lossy())
+    }
+}
+
+/// PAM message styles, see [conv::Prompt].
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub enum MessageStyle {
     PAM_PROMPT_ECHO_OFF = 1,
@@ -62,24 +174,14 @@ pub enum MessageStyle {
     PAM_TEXT_INFO = 4,
 }
 
-/// Sends a message to the user when doing a PAM conversation.
-///
-/// This function assumes the input string has no null bytes in it.
-/// Using a string with a null byte in it will return Err(PamResultCode::PAM_BUF_ERR).
-pub fn info(pamh: PamHandle, msg: String) -> PamResult<()> {
-    let msg = CString::new(msg).map_err(|_| PamResultCode::PAM_BUF_ERR)?;
-    let result_code = unsafe {
-        sys::pam_prompt(
-            pamh,
-            MessageStyle::PAM_TEXT_INFO,
-            ptr::null::<*mut c_char>(),
-            msg.as_ptr(),
-        )
-    };
-
-    match result_code {
-        PamResultCode::PAM_SUCCESS => Ok(()),
-        _ => Err(result_code),
+/// Reports a problem with the module to wherever `diagnostics` says, keeping
+/// quiet towards the user if they asked for silence.
+pub fn diagnose(pamh: Pam<'_>, args: &ModuleArgs, diagnostics: Diagnostics, msg: String) {
+    if diagnostics.user() && !args.silent {
+        let _ = pamh.info(msg.clone());
+    }
+    if diagnostics.syslog() {
+        let _ = pamh.syslog(libc::LOG_ERR, msg);
     }
 }
 
@@ -102,115 +204,652 @@ pub enum PamItemType {
     PAM_AUTHTOK_TYPE = 13,
 }
 
-fn get_item(pamh: PamHandle, item_type: PamItemType) -> PamResult<*const c_void> {
-    let mut raw_item: *const c_void = ptr::null();
-    let r = unsafe { sys::pam_get_item(pamh, item_type, &mut raw_item) };
-    if raw_item.is_null() {
-        Err(r)
-    } else {
-        Ok(raw_item)
-    }
+/// X authentication data, as passed to the X server by display managers.
+#[derive(Debug, Clone)]
+pub struct XAuthData<'a> {
+    /// The authentication method, such as `MIT-MAGIC-COOKIE-1`.
+    pub name: Cow<'a, str>,
+    pub data: &'a [u8],
 }
 
-/// Gets the username that is currently authenticating out of the pam handle.
-///
-/// # Safety
-///
-/// This casts the string directly from C space into Rust space. It relies on
-/// PAM doing things properly. Invalid UTF-8 will be pruned from the result.
-/// Thankfully, PAM usually does things properly.
-pub fn get_user(pamh: PamHandle) -> PamResult<String> {
-    get_item(pamh, PamItemType::PAM_USER).map(|u| unsafe {
-        CStr::from_ptr(u as *const i8)
-            .to_string_lossy()
-            .into_owned()
-    })
+/// The C layout of [XAuthData].
+#[repr(C)]
+struct RawXAuthData {
+    namelen: c_int,
+    name: *const c_char,
+    datalen: c_int,
+    data: *const c_char,
+}
+
+impl<'a> Pam<'a> {
+    /// Writes a message to syslog, prefixed with the name of the PAM service
+    /// the module is running under.
+    ///
+    /// `priority` is one of the syslog levels, such as `libc::LOG_WARNING`.
+    pub fn syslog(self, priority: c_int, msg: String) -> PamResult<()> {
+        let msg = CString::new(msg).map_err(|_| PamResultCode::PAM_BUF_ERR)?;
+        unsafe { sys::pam_syslog(self, priority, c"%s".as_ptr(), msg.as_ptr()) };
+        Ok(())
+    }
+
+    fn get_item(self, item_type: PamItemType) -> PamResult<*const c_void> {
+        let mut raw_item: *const c_void = ptr::null();
+        PamResultCode::check(unsafe { sys::pam_get_item(self, item_type, &mut raw_item) })?;
+        if raw_item.is_null() {
+            Err(PamResultCode::PAM_BAD_ITEM)
+        } else {
+            Ok(raw_item)
+        }
+    }
+
+    /// Borrows a string item from the pam handle, or returns `None` if it
+    /// isn't set or is empty.
+    ///
+    /// # Safety
+    ///
+    /// This borrows the string directly from C space. It relies on PAM doing
+    /// things properly and on nobody setting the item again while the borrow
+    /// lives, which only this module could do during its own callback.
+    /// Invalid UTF-8 is replaced, which is the only case that copies.
+    fn get_string_item(self, item_type: PamItemType) -> Option<Cow<'a, str>> {
+        self.get_item(item_type)
+            .ok()
+            .map(|u| unsafe { CStr::from_ptr(u as *const c_char) }.to_string_lossy())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// The name of the PAM service, such as `sshd` or `sudo`.
+    pub fn service(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_SERVICE)
+    }
+
+    /// The username that is currently authenticating.
+    pub fn user(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_USER)
+    }
+
+    /// The username that is authenticating, asking the application or the
+    /// user for it if nobody has yet. Only `auth` modules should need this.
+    pub fn get_user(self) -> PamResult<Cow<'a, str>> {
+        let mut user: *const c_char = ptr::null();
+        PamResultCode::check(unsafe { sys::pam_get_user(self, &mut user, ptr::null()) })?;
+        if user.is_null() {
+            return Err(PamResultCode::PAM_USER_UNKNOWN);
+        }
+        Ok(unsafe { CStr::from_ptr(user) }.to_string_lossy())
+    }
+
+    /// The terminal the user is logging in on, such as `pts/3` or `:0`.
+    pub fn tty(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_TTY)
+    }
+
+    /// The remote host. This is only set for logins over the network.
+    pub fn rhost(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_RHOST)
+    }
+
+    /// The authentication token, usually the password. This is only available
+    /// to `auth` and `password` modules.
+    pub fn authtok(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_AUTHTOK)
+    }
+
+    /// The old authentication token while a password is being changed.
+    pub fn oldauthtok(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_OLDAUTHTOK)
+    }
+
+    /// The name of the user requesting the service. For `sudo` and `su` this
+    /// is the user running the command, where [Pam::user] is the user they are
+    /// becoming.
+    pub fn ruser(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_RUSER)
+    }
+
+    /// The prompt the application uses when asking for a username.
+    pub fn user_prompt(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_USER_PROMPT)
+    }
+
+    /// The X display the user is logging in on, such as `:0`.
+    pub fn xdisplay(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_XDISPLAY)
+    }
+
+    /// The word used in password prompts, such as `UNIX` in "New UNIX
+    /// password:".
+    pub fn authtok_type(self) -> Option<Cow<'a, str>> {
+        self.get_string_item(PamItemType::PAM_AUTHTOK_TYPE)
+    }
+
+    /// The X authentication data.
+    ///
+    /// # Safety
+    ///
+    /// This borrows the name and data from C space using the lengths PAM
+    /// stores alongside them. It relies on PAM doing things properly.
+    pub fn xauthdata(self) -> Option<XAuthData<'a>> {
+        let raw = self.get_item(PamItemType::PAM_XAUTHDATA).ok()? as *const RawXAuthData;
+        let raw = unsafe { &*raw };
+
+        let bytes = |ptr: *const c_char, len: c_int| -> &'a [u8] {
+            if ptr.is_null() || len <= 0 {
+                return &[];
+            }
+            unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) }
+        };
+
+        Some(XAuthData {
+            name: String::from_utf8_lossy(bytes(raw.name, raw.namelen)),
+            data: bytes(raw.data, raw.datalen),
+        })
+    }
+
+    /// Returns true if the pam handle has a remote host set, which is the case
+    /// for logins over the network but not for local ones like `su` or the
+    /// console.
+    pub fn is_remote(self) -> bool {
+        self.rhost().is_some()
+    }
+
+    /// Stores `data` in the pam handle under `name`, where later callbacks in
+    /// the same PAM transaction can get it back with [Pam::get_data]. PAM drops
+    /// it when the transaction ends.
+    pub fn set_data<T: 'static>(self, name: &CStr, data: T) -> PamResult<()> {
+        extern "C" fn cleanup<T>(_: Pam<'_>, data: *mut c_void, _: c_int) {
+            drop(unsafe { Box::from_raw(data as *mut T) });
+        }
+
+        let data = Box::into_raw(Box::new(data));
+        let r =
+            unsafe { sys::pam_set_data(self, name.as_ptr(), data as *mut c_void, cleanup::<T>) };
+        PamResultCode::check(r).inspect_err(|_| drop(unsafe { Box::from_raw(data) }))
+    }
+
+    /// Gets a copy of data stored with [Pam::set_data] out of the pam handle.
+    ///
+    /// A copy is returned because storing new data under the same name frees
+    /// the old data.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type the data under `name` was stored as. Every name
+    /// this module uses must therefore always be used with the same type.
+    pub unsafe fn get_data<T: Clone>(self, name: &CStr) -> Option<T> {
+        let mut data: *const c_void = ptr::null();
+        PamResultCode::check(sys::pam_get_data(self, name.as_ptr(), &mut data)).ok()?;
+        (!data.is_null()).then(|| (*(data as *const T)).clone())
+    }
 }
 
-/// Gets the remote host out of the pam handle.
+/// Collects the module arguments from the `argc`/`argv` pair PAM passes to
+/// every `pam_sm_*` callback.
 ///
 /// # Safety
 ///
-/// This casts the string directly from C space into Rust space. It relies on
-/// PAM doing things properly. Invalid UTF-8 will be pruned from the result.
-pub fn get_rhost(pamh: PamHandle) -> PamResult<String> {
-    let result = get_item(pamh, PamItemType::PAM_RHOST).map(|u| unsafe {
-        CStr::from_ptr(u as *const i8)
-            .to_string_lossy()
-            .into_owned()
-    })?;
-
-    if result == "".to_string() {
-        return Ok("<unknown>".into());
+/// This relies on PAM handing us `argc` valid C strings. Invalid UTF-8 will be
+/// pruned from the result.
+fn module_args(argc: c_int, argv: *const *const c_char) -> Vec<String> {
+    if argv.is_null() || argc <= 0 {
+        return Vec::new();
     }
 
-    Ok(result)
+    unsafe { std::slice::from_raw_parts(argv, argc as usize) }
+        .iter()
+        .filter(|arg| !arg.is_null())
+        .map(|&arg| {
+            unsafe { CStr::from_ptr(arg) }
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
 }
 
-pub fn discord_webhook(pamh: PamHandle, message: String) -> PamResult<()> {
-    let mut easy = Easy::new();
-    easy.url(include_str!("discordwebhook.txt"))
-        .map_err(|_| PamResultCode::PAM_SYSTEM_ERR)?;
-    easy.http_headers({
-        let mut list = List::new();
-        list.append("User-Agent: pam_rc2022").unwrap();
-        list
-    })
-    .map_err(|_| PamResultCode::PAM_SYSTEM_ERR)?;
-    easy.httppost({
-        let mut form = Form::new();
-        form.part("content")
-            .contents(message.as_bytes())
-            .add()
-            .unwrap();
-        form
-    })
-    .map_err(|_| PamResultCode::PAM_SYSTEM_ERR)?;
-    easy.perform().map_err(|_| PamResultCode::PAM_IGNORE)?;
-    let response_code = easy.response_code().map_err(|why| {
-        let _ = info(pamh, format!("can't perform discord webhook: {}", why));
-        PamResultCode::PAM_SYSTEM_ERR
-    })?;
-    if response_code.div_euclid(100) != 2 {
-        info(
+/// Loads the configuration file, using the path from a `config=` module
+/// argument if one was given.
+///
+/// Without a configuration there is no `diagnostics` setting, so problems
+/// loading it only go to syslog.
+pub fn load_config(pamh: Pam<'_>, args: &ModuleArgs) -> PamResult<Config> {
+    let path = args
+        .config
+        .as_deref()
+        .unwrap_or(config::DEFAULT_CONFIG_PATH);
+
+    Config::load(Path::new(path)).map_err(|why| {
+        diagnose(
             pamh,
-            format!(
-                "can't send message to discord: got status code {}",
-                response_code
-            ),
-        )?;
-        return Err(PamResultCode::PAM_IGNORE);
-    }
-    Ok(())
+            args,
+            Diagnostics::Syslog,
+            format!("can't load {}: {}", path, why),
+        );
+        PamResultCode::PAM_SERVICE_ERR
+    })
 }
 
-pub mod sys {
+mod sys {
     use super::*;
 
     #[link(name = "pam")]
     extern "C" {
-        pub fn pam_prompt(
-            pamh: PamHandle,
-            msg_type: MessageStyle,
-            response: *const *mut c_char,
-            fmt: *const c_char,
-            ...
-        ) -> PamResultCode;
+        pub fn pam_syslog(pamh: Pam<'_>, priority: c_int, fmt: *const c_char, ...);
+        pub fn pam_set_data(
+            pamh: Pam<'_>,
+            module_data_name: *const c_char,
+            data: *mut c_void,
+            cleanup: extern "C" fn(pamh: Pam<'_>, data: *mut c_void, error_status: c_int),
+        ) -> c_int;
+        pub fn pam_get_data(
+            pamh: Pam<'_>,
+            module_data_name: *const c_char,
+            data: *mut *const c_void,
+        ) -> c_int;
+        pub fn pam_get_user(
+            pamh: Pam<'_>,
+            user: *mut *const c_char,
+            prompt: *const c_char,
+        ) -> c_int;
+        pub fn pam_strerror(pamh: *const PamHandle, errnum: c_int) -> *const c_char;
         pub fn pam_get_item(
-            pamh: PamHandle,
+            pamh: Pam<'_>,
             item_type: PamItemType,
             item: *mut *const c_void,
-        ) -> PamResultCode;
+        ) -> c_int;
+    }
+}
+
+pub fn login_message(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let session = session::start(pamh);
+    let event = Event::new(pamh, EventKind::Login, Some(&session));
+    if config.access.max_sessions.is_some() {
+        if let Err(why) = session::register(&config.state_dir, &event.user, &session) {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't register session: {}", why),
+            );
+        }
+    }
+    if !should_notify(pamh, args, config, &event) {
+        return Ok(());
+    }
+
+    let window = match config.rate_limit.window {
+        Some(window) => window.as_secs(),
+        None => return send_event(pamh, args, config, event),
+    };
+    let key = format!("{}@{}/{}", event.user, event.rhost, event.service);
+
+    let events = state::update(&config.state_dir, "logins.json", |digests: &mut Digests| {
+        let mut events = digests.take_expired(event.time, window, EventKind::LoginSummary);
+        if digests.record(&key, &event, window) {
+            events.push(event.clone());
+        } else {
+            session::keep_quiet(pamh, &session);
+            skip_event(
+                pamh,
+                args,
+                config,
+                &event,
+                Outcome::RateLimited,
+                "rate_limit",
+            );
+        }
+        events
+    })
+    .unwrap_or_else(|why| {
+        diagnose(
+            pamh,
+            args,
+            config.diagnostics,
+            format!("can't update login state: {}", why),
+        );
+        vec![event]
+    });
+
+    send_events(pamh, args, config, events)
+}
+
+pub fn logout_message(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let session = session::finish(pamh);
+    let event = Event::new(pamh, EventKind::Logout, session.as_ref());
+    if let (Some(session), Some(_)) = (&session, config.access.max_sessions) {
+        if let Err(why) = session::unregister(&config.state_dir, &event.user, session) {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't unregister session: {}", why),
+            );
+        }
+    }
+    if session.as_ref().is_some_and(|session| session.quiet) {
+        skip_event(
+            pamh,
+            args,
+            config,
+            &event,
+            Outcome::RateLimited,
+            "rate_limit",
+        );
+        return Ok(());
+    }
+    if !should_notify(pamh, args, config, &event) {
+        return Ok(());
     }
+    send_event(pamh, args, config, event)
 }
 
-pub fn login_message(pamh: PamHandle) -> PamResult<()> {
-    discord_webhook(
-        pamh,
-        format!("{} logging in from {}", get_user(pamh)?, get_rhost(pamh)?),
+/// Reports a failed authentication, collapsing bursts of failures from the same
+/// remote host into one summary per `[auth_fail]` window.
+///
+/// The failure also counts towards `[lockout]`, whether or not it is filtered
+/// out, and any lockout it leads to is reported along with it.
+pub fn auth_fail_message(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let event = Event::new(pamh, EventKind::AuthFailure, None);
+    let lockouts = lockout::record_failure(pamh, args, config, &event);
+    if !should_notify(pamh, args, config, &event) {
+        return send_events(pamh, args, config, lockouts);
+    }
+    let window = config.auth_fail.window.as_secs();
+    let key = format!("{}@{}", event.service, event.rhost);
+
+    let events = state::update(
+        &config.state_dir,
+        "auth_fail.json",
+        |digests: &mut Digests| {
+            let mut events =
+                digests.take_expired(event.time, window, EventKind::AuthFailureSummary);
+            if digests.record(&key, &event, window) {
+                events.push(event.clone());
+            } else {
+                skip_event(
+                    pamh,
+                    args,
+                    config,
+                    &event,
+                    Outcome::RateLimited,
+                    "auth_fail",
+                );
+            }
+            events
+        },
+    )
+    .unwrap_or_else(|why| {
+        diagnose(
+            pamh,
+            args,
+            config.diagnostics,
+            format!("can't update failure state: {}", why),
+        );
+        vec![event]
+    });
+
+    send_events(pamh, args, config, [events, lockouts].concat())
+}
+
+/// Asks for whatever the `totp` and `approve` module arguments say, in that
+/// order.
+fn second_factors(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let mut result = Ok(());
+    if args.totp {
+        result = totp::authenticate(pamh, args, config);
+    }
+    // Users let through by nullok still need approval.
+    if args.approve && matches!(result, Ok(()) | Err(PamResultCode::PAM_IGNORE)) {
+        result = approve::authenticate(pamh, args, config);
+    }
+    result
+}
+
+/// Closes the `[auth_fail]` and `[rate_limit]` windows that are over and
+/// returns their summaries.
+///
+/// Summaries are normally sent by the next event of the same kind from
+/// anywhere, this is for sending them when no more happen.
+pub fn expired_summaries(config: &Config) -> std::io::Result<Vec<Event>> {
+    let now = now();
+    let mut events = state::update(
+        &config.state_dir,
+        "auth_fail.json",
+        |digests: &mut Digests| {
+            digests.take_expired(
+                now,
+                config.auth_fail.window.as_secs(),
+                EventKind::AuthFailureSummary,
+            )
+        },
     )?;
+    if let Some(window) = config.rate_limit.window {
+        events.extend(state::update(
+            &config.state_dir,
+            "logins.json",
+            |digests: &mut Digests| {
+                digests.take_expired(now, window.as_secs(), EventKind::LoginSummary)
+            },
+        )?);
+    }
+    Ok(events)
+}
+
+/// Returns true if `event` passes the `only_remote` module argument and the
+/// `[filter]` rules. This is checked before anything is recorded, so filtered
+/// events don't count towards summaries either.
+pub(crate) fn should_notify(
+    pamh: Pam<'_>,
+    args: &ModuleArgs,
+    config: &Config,
+    event: &Event,
+) -> bool {
+    let reason = if args.only_remote && event.rhost.is_empty() {
+        "local"
+    } else if !config.filter.allows(event) {
+        "filter"
+    } else {
+        return true;
+    };
+
+    skip_event(pamh, args, config, event, Outcome::Filtered, reason);
+    false
+}
+
+/// Logs and audits an event that isn't going to be sent.
+fn skip_event(
+    pamh: Pam<'_>,
+    args: &ModuleArgs,
+    config: &Config,
+    event: &Event,
+    outcome: Outcome,
+    reason: &str,
+) {
+    let logger = Logger::new(pamh, args.debug);
+    logger.debug(
+        Message::new("not sending event")
+            .field("event", &event.id)
+            .field("kind", event.kind.name())
+            .field("reason", reason),
+    );
+    audit(&logger, config, event, outcome, &Deliveries::new());
+}
+
+/// Writes `event` to the `[audit]` log, logging any problem with it.
+pub(crate) fn audit(
+    logger: &Logger,
+    config: &Config,
+    event: &Event,
+    outcome: Outcome,
+    deliveries: &Deliveries,
+) {
+    if let Err(why) = audit::write(&config.audit, event, outcome, deliveries) {
+        logger.error(
+            Message::new("can't write audit log")
+                .field("event", &event.id)
+                .field("error", why),
+        );
+    }
+}
+
+/// Hands `event` to a detached process that sends it to every notifier.
+fn send_event(pamh: Pam<'_>, args: &ModuleArgs, config: &Config, event: Event) -> PamResult<()> {
+    send_events(pamh, args, config, vec![event])
+}
+
+/// The notifiers events are sent to.
+pub(crate) fn notifiers(args: &ModuleArgs, config: &Config) -> Vec<Box<dyn Notifier>> {
+    // A webhook= module argument replaces the configured notifiers for this service.
+    match &args.webhook {
+        Some(url) => vec![Box::new(Discord::new(
+            "webhook",
+            url,
+            notifier::discord_format(config),
+        ))],
+        None => notifier::from_config(config),
+    }
+}
 
-    Ok(())
+/// Hands `events` to a detached process that sends them to every notifier.
+fn send_events(
+    pamh: Pam<'_>,
+    args: &ModuleArgs,
+    config: &Config,
+    events: Vec<Event>,
+) -> PamResult<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let notifiers = notifiers(args, config);
+    let logger = Logger::new(pamh, args.debug);
+    if notifiers.is_empty() {
+        for event in &events {
+            audit(
+                &logger,
+                config,
+                event,
+                Outcome::NoNotifiers,
+                &Deliveries::new(),
+            );
+        }
+        return Ok(());
+    }
+
+    for event in &events {
+        logger.debug(
+            Message::new("sending event")
+                .field("event", &event.id)
+                .field("kind", event.kind.name())
+                .field("notifiers", notifiers.len()),
+        );
+    }
+
+    // Recorded before detaching, since the detached process may be killed
+    // at the timeout before it gets to write the outcome.
+    for event in &events {
+        audit(&logger, config, event, Outcome::Pending, &Deliveries::new());
+    }
+    detach::detached(config.delivery.timeout, || {
+        deliver_detached(logger, config, &notifiers, &events)
+    })
+    .map_err(|why| {
+        diagnose(
+            pamh,
+            args,
+            config.diagnostics,
+            format!("can't start sending messages: {}", why),
+        );
+        for event in &events {
+            audit(&logger, config, event, Outcome::Failed, &Deliveries::new());
+        }
+        PamResultCode::PAM_SYSTEM_ERR
+    })
+}
+
+/// Sends `events` to every notifier, spooling the requests that fail.
+/// How each delivery went is passed to `log` and returned for each event.
+pub fn deliver(
+    config: &Config,
+    notifiers: &[Box<dyn Notifier>],
+    events: &[Event],
+    log: &mut Log,
+) -> Vec<Deliveries> {
+    let mut deliveries = vec![Deliveries::new(); events.len()];
+    for notifier in notifiers {
+        for (event, deliveries) in events.iter().zip(&mut deliveries) {
+            let request = notifier.request(event);
+            let started = std::time::Instant::now();
+            let result = http::send(&config.http, &request);
+            let message = |text: &str| {
+                Message::new(text)
+                    .field("event", &event.id)
+                    .field("notifier", notifier.name())
+                    .field("latency_ms", started.elapsed().as_millis())
+            };
+
+            let why = match result {
+                Ok(status) => {
+                    log(
+                        Level::Debug,
+                        message("sent message").field("status", status).into(),
+                    );
+                    deliveries.insert(notifier.name().to_string(), Delivery::Sent);
+                    continue;
+                }
+                Err(why) => why,
+            };
+            let mut failed = message("can't send message");
+            if let http::HttpError::Status(status) = why {
+                failed = failed.field("status", status);
+            }
+            log(Level::Warning, failed.field("error", &why).into());
+
+            let delivery = match spool::push(&config.spool, notifier.name(), request) {
+                Ok(_) => Delivery::Spooled,
+                Err(why) => {
+                    log(
+                        Level::Error,
+                        message("can't spool message").field("error", why).into(),
+                    );
+                    Delivery::Failed
+                }
+            };
+            deliveries.insert(notifier.name().to_string(), delivery);
+        }
+    }
+
+    deliveries
+}
+
+/// Delivers `events` and then retries whatever in the spool is due.
+///
+/// This runs in a detached process, so there is nobody left to return an
+/// error to and problems are logged instead. The user's terminal may be gone
+/// by now, so they always go to syslog whatever `diagnostics` says.
+fn deliver_detached(
+    logger: Logger,
+    config: &Config,
+    notifiers: &[Box<dyn Notifier>],
+    events: &[Event],
+) {
+    let mut log = |level: Level, msg: String| logger.log(level, msg);
+
+    let deliveries = deliver(config, notifiers, events, &mut log);
+    for (event, deliveries) in events.iter().zip(&deliveries) {
+        audit(&logger, config, event, Outcome::of(deliveries), deliveries);
+    }
+
+    match spool::flush(&config.spool, &config.http, &mut log) {
+        Ok(stats) => logger.debug(
+            Message::new("flushed spool")
+                .field("sent", stats.sent)
+                .field("failed", stats.failed)
+                .field("waiting", stats.waiting)
+                .field("dropped", stats.dropped),
+        ),
+        Err(why) if why.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(why) => logger.error(Message::new("can't flush spool").field("error", why)),
+    }
 }
 
 mod callbacks {
@@ -218,64 +857,148 @@ mod callbacks {
 
     #[no_mangle]
     pub extern "C" fn pam_sm_acct_mgmt(
-        _: PamHandle,
-        _: PamFlags,
-        _: c_int,
-        _: *const *const c_char,
-    ) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+        pamh: Pam<'_>,
+        flags: PamFlags,
+        argc: c_int,
+        argv: *const *const c_char,
+    ) -> c_int {
+        let args = ModuleArgs::new(pamh, flags, &module_args(argc, argv));
+        let result = load_config(pamh, &args).and_then(|config| {
+            let result = access::check_login(pamh, &args, &config);
+            if matches!(result, Ok(()) | Err(PamResultCode::PAM_IGNORE)) {
+                lockout::forget_failures(pamh, &args, &config);
+            }
+            result
+        });
+        match result {
+            Ok(()) => PamResultCode::PAM_SUCCESS,
+            Err(why) => why,
+        }
+        .into()
     }
 
     #[no_mangle]
     pub extern "C" fn pam_sm_authenticate(
-        _: PamHandle,
-        _: PamFlags,
-        _: c_int,
-        _: *const *const c_char,
-    ) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+        pamh: Pam<'_>,
+        flags: PamFlags,
+        argc: c_int,
+        argv: *const *const c_char,
+    ) -> c_int {
+        let args = ModuleArgs::new(pamh, flags, &module_args(argc, argv));
+        if args.lockout {
+            if let Err(why) = load_config(pamh, &args)
+                .and_then(|config| lockout::check_login(pamh, &args, &config))
+            {
+                return why.into();
+            }
+        }
+        if args.totp || args.approve {
+            let result = load_config(pamh, &args).and_then(|config| {
+                second_factors(pamh, &args, &config).inspect_err(|&why| {
+                    if args.auth_fail && why == PamResultCode::PAM_AUTH_ERR {
+                        let _ = auth_fail_message(pamh, &args, &config);
+                    }
+                })
+            });
+            return match result {
+                Ok(()) => PamResultCode::PAM_SUCCESS,
+                Err(why) => why,
+            }
+            .into();
+        }
+        if !args.auth_fail {
+            return PamResultCode::PAM_IGNORE.into();
+        }
+
+        // The stack only reaches us in auth_fail mode when authentication has
+        // already failed, so it has to stay failed whatever happens here.
+        let _ = load_config(pamh, &args).and_then(|config| auth_fail_message(pamh, &args, &config));
+        PamResultCode::PAM_AUTH_ERR.into()
     }
 
     #[no_mangle]
     pub extern "C" fn pam_sm_chauthtok(
-        _: PamHandle,
-        _: PamFlags,
-        _: c_int,
-        _: *const *const c_char,
-    ) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+        pamh: Pam<'_>,
+        flags: PamFlags,
+        argc: c_int,
+        argv: *const *const c_char,
+    ) -> c_int {
+        let _ = ModuleArgs::new(pamh, flags, &module_args(argc, argv));
+        PamResultCode::PAM_IGNORE.into()
     }
 
     #[no_mangle]
     pub extern "C" fn pam_sm_close_session(
-        _: PamHandle,
-        _: PamFlags,
-        _: c_int,
-        _: *const *const c_char,
-    ) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+        pamh: Pam<'_>,
+        flags: PamFlags,
+        argc: c_int,
+        argv: *const *const c_char,
+    ) -> c_int {
+        let args = ModuleArgs::new(pamh, flags, &module_args(argc, argv));
+        match load_config(pamh, &args).and_then(|config| logout_message(pamh, &args, &config)) {
+            Ok(_) => PamResultCode::PAM_IGNORE,
+            Err(why) => why,
+        }
+        .into()
     }
 
     #[no_mangle]
     pub extern "C" fn pam_sm_open_session(
-        pamh: PamHandle,
-        _: PamFlags,
-        _: c_int,
-        _: *const *const c_char,
-    ) -> PamResultCode {
-        match login_message(pamh) {
+        pamh: Pam<'_>,
+        flags: PamFlags,
+        argc: c_int,
+        argv: *const *const c_char,
+    ) -> c_int {
+        let args = ModuleArgs::new(pamh, flags, &module_args(argc, argv));
+        match load_config(pamh, &args).and_then(|config| login_message(pamh, &args, &config)) {
             Ok(_) => PamResultCode::PAM_IGNORE,
             Err(why) => why,
         }
+        .into()
     }
 
     #[no_mangle]
     pub extern "C" fn pam_sm_setcred(
-        _: PamHandle,
-        _: PamFlags,
-        _: c_int,
-        _: *const *const c_char,
-    ) -> PamResultCode {
-        PamResultCode::PAM_IGNORE
+        pamh: Pam<'_>,
+        flags: PamFlags,
+        argc: c_int,
+        argv: *const *const c_char,
+    ) -> c_int {
+        let _ = ModuleArgs::new(pamh, flags, &module_args(argc, argv));
+        PamResultCode::PAM_IGNORE.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_result_codes_round_trip() {
+        for code in 0..=31 {
+            let known = PamResultCode::try_from(code).unwrap();
+            assert_ne!(known, PamResultCode::Unknown(code));
+            assert_eq!(c_int::from(known), code);
+            assert_eq!(PamResultCode::from_raw(code), known);
+        }
+        assert_eq!(PamResultCode::try_from(7), Ok(PamResultCode::PAM_AUTH_ERR));
+        assert_eq!(c_int::from(PamResultCode::PAM_IGNORE), 25);
+    }
+
+    #[test]
+    fn unknown_result_codes_round_trip() {
+        for code in [-1, 32, 1000, c_int::MIN, c_int::MAX] {
+            assert_eq!(PamResultCode::try_from(code), Err(code));
+            let unknown = PamResultCode::from_raw(code);
+            assert_eq!(unknown, PamResultCode::Unknown(code));
+            assert_eq!(c_int::from(unknown), code);
+        }
+    }
+
+    #[test]
+    fn check_only_accepts_success() {
+        assert_eq!(PamResultCode::check(0), Ok(()));
+        assert_eq!(PamResultCode::check(25), Err(PamResultCode::PAM_IGNORE));
+        assert_eq!(PamResultCode::check(-3), Err(PamResultCode::Unknown(-3)));
     }
 }
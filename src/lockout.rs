@@ -0,0 +1,269 @@
+//! Temporary lockouts after repeated failed authentications, like
+//! pam_faillock.
+//!
+//! Every failure reported with the `auth_fail` module argument counts against
+//! the user and the remote host it came from. Once either has `deny` failures
+//! within `interval`, it is locked for `unlock_time`, which the `lockout`
+//! module argument enforces. Locks are identified by keys like `user:alice`
+//! and `rhost:192.0.2.1`, which `pam_rc2022ctl lockouts` lists and
+//! `pam_rc2022ctl unlock` clears.
+
+use crate::{
+    args::ModuleArgs,
+    config::Config,
+    diagnose,
+    event::{self, format_duration, now, Event, EventKind},
+    log::{Logger, Message},
+    should_notify, state, Pam, PamResult, PamResultCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io};
+
+/// Where failures and locks are kept, in the state directory.
+const FILE: &str = "lockout.json";
+
+/// The failures and lock of one user or remote host.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// When each failure within `interval` happened, in seconds since the
+    /// Unix epoch.
+    pub failures: Vec<u64>,
+    /// When the lock ends, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<u64>,
+}
+
+impl Entry {
+    fn is_locked(&self, now: u64) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+}
+
+type Entries = BTreeMap<String, Entry>;
+
+/// A lock that was just put in place.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub key: String,
+    /// How many failures led to it.
+    pub failures: u32,
+}
+
+/// The keys failures of `user` from `rhost` count against. Root is left out
+/// unless `even_deny_root` is set, and local logins have no host key.
+pub fn keys(config: &Config, user: &str, rhost: &str) -> Vec<String> {
+    let lockout = &config.lockout;
+    let mut keys = Vec::new();
+    if lockout.track_user && (user != "root" || lockout.even_deny_root) {
+        keys.push(format!("user:{}", user));
+    }
+    if lockout.track_rhost && !rhost.is_empty() {
+        keys.push(format!("rhost:{}", rhost));
+    }
+    keys
+}
+
+/// Counts a failure against every key in `keys` and returns the locks that
+/// puts in place.
+pub fn record(config: &Config, keys: &[String], now: u64) -> io::Result<Vec<Lock>> {
+    let lockout = &config.lockout;
+    let deny = match lockout.deny {
+        Some(deny) => deny,
+        None => return Ok(Vec::new()),
+    };
+
+    state::update(&config.state_dir, FILE, |entries: &mut Entries| {
+        prune(entries, now, lockout.interval.as_secs());
+
+        let mut locks = Vec::new();
+        for key in keys {
+            let entry = entries.entry(key.clone()).or_default();
+            if entry.is_locked(now) {
+                continue;
+            }
+            entry.failures.push(now);
+            if entry.failures.len() >= deny as usize {
+                locks.push(Lock {
+                    key: key.clone(),
+                    failures: entry.failures.len() as u32,
+                });
+                entry.failures.clear();
+                entry.locked_until = Some(now.saturating_add(lockout.unlock_time.as_secs()));
+            }
+        }
+        locks
+    })
+}
+
+/// Returns the first of `keys` that is locked and when its lock ends.
+pub fn locked(config: &Config, keys: &[String], now: u64) -> io::Result<Option<(String, u64)>> {
+    let interval = config.lockout.interval.as_secs();
+    state::update(&config.state_dir, FILE, |entries: &mut Entries| {
+        prune(entries, now, interval);
+        keys.iter().find_map(|key| {
+            let entry = entries.get(key)?;
+            entry
+                .locked_until
+                .filter(|_| entry.is_locked(now))
+                .map(|until| (key.clone(), until))
+        })
+    })
+}
+
+/// Forgets the failures counted against `key`, after a successful login. A
+/// lock stays in place.
+pub fn reset(config: &Config, key: &str) -> io::Result<()> {
+    state::update(&config.state_dir, FILE, |entries: &mut Entries| {
+        if let Some(entry) = entries.get_mut(key) {
+            entry.failures.clear();
+            if entry.locked_until.is_none() {
+                entries.remove(key);
+            }
+        }
+    })
+}
+
+/// Every user and remote host with failures or a lock.
+pub fn list(config: &Config, now: u64) -> io::Result<Entries> {
+    let interval = config.lockout.interval.as_secs();
+    state::update(&config.state_dir, FILE, |entries: &mut Entries| {
+        prune(entries, now, interval);
+        entries.clone()
+    })
+}
+
+/// Removes the failures and lock of `key`, or of everyone if it's `None`.
+/// Returns how many entries were removed.
+pub fn clear(config: &Config, key: Option<&str>) -> io::Result<usize> {
+    state::update(&config.state_dir, FILE, |entries: &mut Entries| match key {
+        Some(key) => entries.remove(key).map_or(0, |_| 1),
+        None => std::mem::take(entries).len(),
+    })
+}
+
+/// Drops failures older than `interval` and locks that have ended.
+fn prune(entries: &mut Entries, now: u64, interval: u64) {
+    entries.retain(|_, entry| {
+        entry
+            .failures
+            .retain(|&time| time.saturating_add(interval) > now);
+        if !entry.is_locked(now) {
+            entry.locked_until = None;
+        }
+        !entry.failures.is_empty() || entry.locked_until.is_some()
+    });
+}
+
+/// Counts the failed authentication `event` towards `[lockout]` and returns
+/// events for the lockouts it leads to.
+pub fn record_failure(
+    pamh: Pam<'_>,
+    args: &ModuleArgs,
+    config: &Config,
+    event: &Event,
+) -> Vec<Event> {
+    let keys = keys(config, &event.user, &event.rhost);
+    let locks = match record(config, &keys, event.time) {
+        Ok(locks) => locks,
+        Err(why) => {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't update lockout state: {}", why),
+            );
+            return Vec::new();
+        }
+    };
+
+    let logger = Logger::new(pamh, args.debug);
+    let unlock_time = config.lockout.unlock_time.as_secs();
+    locks
+        .into_iter()
+        .map(|lock| {
+            logger.warning(
+                Message::new("locked out")
+                    .field("lock", &lock.key)
+                    .field("failures", lock.failures)
+                    .field("for", format_duration(unlock_time)),
+            );
+            Event {
+                id: event::new_id(),
+                kind: EventKind::Lockout,
+                duration: Some(unlock_time),
+                count: Some(lock.failures),
+                lock: Some(lock.key),
+                ..event.clone()
+            }
+        })
+        .filter(|event| should_notify(pamh, args, config, event))
+        .collect()
+}
+
+/// Checks whether the user logging in or their remote host is locked out.
+///
+/// Fails with `PAM_MAXTRIES` if the user is locked out, and with
+/// `PAM_AUTH_ERR` if the remote host they log in from is.
+pub fn check_login(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    if config.lockout.deny.is_none() {
+        return Ok(());
+    }
+    let user = pamh.get_user()?;
+    let rhost = pamh.rhost().unwrap_or_default();
+    let now = now();
+
+    let keys = keys(config, &user, &rhost);
+    let (key, until) = match locked(config, &keys, now) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => return Ok(()),
+        Err(why) => {
+            // Like the rest of the state, locks are best effort.
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't read lockout state: {}", why),
+            );
+            return Ok(());
+        }
+    };
+
+    let left = format_duration(until.saturating_sub(now));
+    Logger::new(pamh, args.debug).warning(
+        Message::new("refused locked out login")
+            .field("user", &user)
+            .field("rhost", &rhost)
+            .field("lock", &key)
+            .field("left", &left),
+    );
+    if !args.silent {
+        let _ = pamh.error_msg(format!("Too many failed logins, try again in {}.", left));
+    }
+    if key.starts_with("user:") {
+        Err(PamResultCode::PAM_MAXTRIES)
+    } else {
+        Err(PamResultCode::PAM_AUTH_ERR)
+    }
+}
+
+/// Forgets the failed authentications of the user logging in once they are
+/// let in, so only failures in a row lead to a lockout.
+pub fn forget_failures(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) {
+    if config.lockout.deny.is_none() {
+        return;
+    }
+    let user = match pamh.user() {
+        Some(user) => user,
+        None => return,
+    };
+    for key in keys(config, &user, "") {
+        if let Err(why) = reset(config, &key) {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't update lockout state: {}", why),
+            );
+        }
+    }
+}
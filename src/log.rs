@@ -0,0 +1,121 @@
+//! Logging for administrators.
+//!
+//! Messages go to syslog through `pam_syslog`, which prefixes them with the
+//! module and service name. Details are appended as `key=value` fields so they
+//! can be searched for in the journal:
+//!
+//! ```text
+//! pam_rc2022(sshd:session): can't send message event=1657129020-4711-1 notifier=ops status=503 latency_ms=231
+//! ```
+//!
+//! Debug messages are only logged with the `debug` module argument.
+
+use crate::Pam;
+use std::{fmt, os::raw::c_int};
+
+/// How important a log message is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// The syslog priority for the level, such as `libc::LOG_ERR`.
+    pub fn priority(self) -> c_int {
+        match self {
+            Level::Error => libc::LOG_ERR,
+            Level::Warning => libc::LOG_WARNING,
+            Level::Info => libc::LOG_INFO,
+            Level::Debug => libc::LOG_DEBUG,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        })
+    }
+}
+
+/// Somewhere log messages can go. The module logs to syslog with a [Logger],
+/// `pam_rc2022ctl` prints them.
+pub type Log<'a> = dyn FnMut(Level, String) + 'a;
+
+/// A log message with `key=value` fields.
+#[derive(Debug, Clone)]
+pub struct Message(String);
+
+impl Message {
+    pub fn new(text: impl Into<String>) -> Message {
+        Message(text.into())
+    }
+
+    /// Appends a field. Values that are empty or contain spaces, quotes or
+    /// `=` are quoted.
+    pub fn field(mut self, key: &str, value: impl fmt::Display) -> Message {
+        let value = value.to_string();
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+            self.0 += &format!(" {}={:?}", key, value);
+        } else {
+            self.0 += &format!(" {}={}", key, value);
+        }
+        self
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Message> for String {
+    fn from(message: Message) -> String {
+        message.0
+    }
+}
+
+/// Logs to syslog on behalf of a pam handle.
+#[derive(Clone, Copy)]
+pub struct Logger<'a> {
+    pamh: Pam<'a>,
+    debug: bool,
+}
+
+impl<'a> Logger<'a> {
+    /// Creates a logger that drops debug messages unless `debug` is set.
+    pub fn new(pamh: Pam<'a>, debug: bool) -> Logger<'a> {
+        Logger { pamh, debug }
+    }
+
+    pub fn log(&self, level: Level, msg: impl Into<String>) {
+        if level == Level::Debug && !self.debug {
+            return;
+        }
+        let _ = self.pamh.syslog(level.priority(), msg.into());
+    }
+
+    pub fn error(&self, msg: impl Into<String>) {
+        self.log(Level::Error, msg)
+    }
+
+    pub fn warning(&self, msg: impl Into<String>) {
+        self.log(Level::Warning, msg)
+    }
+
+    pub fn info(&self, msg: impl Into<String>) {
+        self.log(Level::Info, msg)
+    }
+
+    pub fn debug(&self, msg: impl Into<String>) {
+        self.log(Level::Debug, msg)
+    }
+}
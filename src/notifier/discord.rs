@@ -0,0 +1,122 @@
+use super::{Format, Notifier};
+use crate::{
+    config::NotifierConfig,
+    event::{format_duration, Event, EventKind},
+    http::{Body, Request},
+    template::{utc_time, Escape},
+};
+use serde_json::{json, Value};
+
+/// Posts messages to a Discord webhook, either as plain text or as a rich
+/// embed with a field for every detail of the event.
+pub struct Discord {
+    name: String,
+    format: Format,
+    url: String,
+    embed: bool,
+    username: Option<String>,
+    avatar_url: Option<String>,
+}
+
+impl Discord {
+    /// Creates a notifier that sends plain text messages to `url`.
+    pub fn new(name: &str, url: &str, format: Format) -> Discord {
+        Discord {
+            name: name.to_string(),
+            format,
+            url: url.to_string(),
+            embed: false,
+            username: None,
+            avatar_url: None,
+        }
+    }
+
+    /// Creates a notifier from a `[notifier.NAME]` section.
+    pub fn from_config(config: &NotifierConfig, format: Format) -> Discord {
+        Discord {
+            embed: config.embed,
+            username: config.username.clone(),
+            avatar_url: config.avatar_url.clone(),
+            ..Discord::new(&config.name, &config.url, format)
+        }
+    }
+
+    fn embed(&self, event: &Event) -> Value {
+        let details = [
+            ("User", event.who()),
+            ("Source", event.rhost.clone()),
+            ("Service", event.service.clone()),
+            ("TTY", event.tty.clone()),
+            ("Host", event.hostname.clone()),
+            (
+                "Duration",
+                event.duration.map(format_duration).unwrap_or_default(),
+            ),
+            (
+                "Count",
+                event.count.map(|c| c.to_string()).unwrap_or_default(),
+            ),
+        ];
+        // Discord rejects fields with empty values.
+        let fields: Vec<Value> = details
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "value": Escape::Markdown.apply(value),
+                    "inline": true,
+                })
+            })
+            .collect();
+
+        json!({
+            "title": self.format.message(event),
+            "color": colour(event),
+            "timestamp": utc_time(event.time),
+            "fields": fields,
+        })
+    }
+}
+
+/// The colour down the side of an embed, as `0xRRGGBB`.
+fn colour(event: &Event) -> u32 {
+    match event.kind {
+        EventKind::Login if event.user == "root" => 0xe67e22,
+        EventKind::Login => 0x2ecc71,
+        EventKind::Logout => 0x95a5a6,
+        EventKind::AuthFailure => 0xe74c3c,
+        EventKind::AuthFailureSummary => 0x992d22,
+        EventKind::LoginSummary => 0x1f8b4c,
+        EventKind::ApprovalRequest => 0x3498db,
+        EventKind::Lockout => 0x8e44ad,
+    }
+}
+
+impl Notifier for Discord {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        if self.embed {
+            let mut payload = json!({ "embeds": [self.embed(event)] });
+            if let Some(username) = &self.username {
+                payload["username"] = json!(username);
+            }
+            if let Some(avatar_url) = &self.avatar_url {
+                payload["avatar_url"] = json!(avatar_url);
+            }
+            return Request::post(&self.url, Body::Json(payload));
+        }
+
+        let mut parts = vec![("content".to_string(), self.format.message(event))];
+        if let Some(username) = &self.username {
+            parts.push(("username".to_string(), username.clone()));
+        }
+        if let Some(avatar_url) = &self.avatar_url {
+            parts.push(("avatar_url".to_string(), avatar_url.clone()));
+        }
+        Request::post(&self.url, Body::Form(parts))
+    }
+}
@@ -0,0 +1,44 @@
+use super::{Format, Notifier};
+use crate::{
+    config::NotifierConfig,
+    event::Event,
+    http::{Body, Request},
+};
+use serde_json::json;
+
+/// Pushes messages to a Gotify server.
+pub struct Gotify {
+    name: String,
+    format: Format,
+    url: String,
+    token: String,
+    priority: u8,
+}
+
+impl Gotify {
+    pub fn new(config: &NotifierConfig, format: Format) -> Gotify {
+        Gotify {
+            name: config.name.clone(),
+            format,
+            url: format!("{}/message", config.url.trim_end_matches('/')),
+            token: config.token.clone().unwrap_or_default(),
+            priority: config.priority.unwrap_or(5),
+        }
+    }
+}
+
+impl Notifier for Gotify {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        let payload = json!({
+            "title": format!("pam_rc2022 on {}", event.hostname),
+            "message": self.format.message(event),
+            "priority": self.priority,
+        });
+
+        Request::post(&self.url, Body::Json(payload)).header("X-Gotify-Key", &self.token)
+    }
+}
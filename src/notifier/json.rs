@@ -0,0 +1,45 @@
+use super::{Format, Notifier};
+use crate::{
+    config::NotifierConfig,
+    event::Event,
+    http::{Body, Request},
+};
+
+/// POSTs the event as a JSON object to any URL.
+///
+/// The object has every field of [Event] plus a `message` field with the
+/// human readable text other notifiers send.
+pub struct Json {
+    name: String,
+    format: Format,
+    url: String,
+    headers: Vec<String>,
+}
+
+impl Json {
+    pub fn new(config: &NotifierConfig, format: Format) -> Json {
+        Json {
+            name: config.name.clone(),
+            format,
+            url: config.url.clone(),
+            headers: config.headers.clone(),
+        }
+    }
+}
+
+impl Notifier for Json {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        // Serializing a struct of strings and integers can't fail.
+        let mut payload = serde_json::to_value(event).unwrap();
+        payload["message"] = self.format.message(event).into();
+
+        let mut request = Request::post(&self.url, Body::Json(payload));
+        request.headers.extend(self.headers.iter().cloned());
+
+        request
+    }
+}
@@ -0,0 +1,60 @@
+use super::{Format, Notifier};
+use crate::{
+    config::NotifierConfig,
+    event::Event,
+    http::{encode_path_segment, Body, Request},
+};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sends `m.text` messages to a Matrix room with the client-server API.
+pub struct Matrix {
+    name: String,
+    format: Format,
+    homeserver: String,
+    token: String,
+    room: String,
+}
+
+impl Matrix {
+    pub fn new(config: &NotifierConfig, format: Format) -> Matrix {
+        Matrix {
+            name: config.name.clone(),
+            format,
+            homeserver: config.url.trim_end_matches('/').to_string(),
+            token: config.token.clone().unwrap_or_default(),
+            room: config.room.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl Notifier for Matrix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        // The transaction ID only has to be unique per access token, so that
+        // retries of the same request aren't delivered twice.
+        let txn_id = format!(
+            "pam_rc2022.{}.{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+            std::process::id()
+        );
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver,
+            encode_path_segment(&self.room),
+            txn_id
+        );
+
+        Request::put(
+            url,
+            Body::Json(json!({ "msgtype": "m.text", "body": self.format.message(event) })),
+        )
+        .header("Authorization", &format!("Bearer {}", self.token))
+    }
+}
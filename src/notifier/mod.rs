@@ -0,0 +1,145 @@
+//! Backends that login events can be sent to.
+//!
+//! Every `webhook =` line in the configuration becomes a [Discord] notifier,
+//! and every `[notifier.NAME]` section becomes whatever its `type` says.
+
+use crate::{
+    config::{Config, HttpConfig, NotifierConfig, NotifierKind},
+    event::Event,
+    http::{self, HttpError, Request, Signing},
+    template::{Escape, Templates},
+};
+
+mod discord;
+mod gotify;
+mod json;
+mod matrix;
+mod ntfy;
+mod slack;
+
+pub use discord::Discord;
+pub use gotify::Gotify;
+pub use json::Json;
+pub use matrix::Matrix;
+pub use ntfy::Ntfy;
+pub use slack::Slack;
+
+/// Something that can tell people about an [Event].
+pub trait Notifier {
+    /// The name of the notifier, used in diagnostics.
+    fn name(&self) -> &str;
+
+    /// Builds the request that tells this notifier's service about `event`.
+    fn request(&self, event: &Event) -> Request;
+
+    /// Sends `event` to wherever this notifier sends things.
+    fn notify(&self, config: &HttpConfig, event: &Event) -> Result<(), HttpError> {
+        http::send(config, &self.request(event)).map(|_| ())
+    }
+}
+
+/// How a notifier turns events into message text.
+#[derive(Debug, Clone)]
+pub struct Format {
+    pub templates: Templates,
+    pub escape: Escape,
+}
+
+impl Format {
+    /// Renders the message text for `event`.
+    pub fn message(&self, event: &Event) -> String {
+        self.templates.render(event, self.escape)
+    }
+}
+
+/// Builds every notifier the configuration enables.
+pub fn from_config(config: &Config) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+    for url in &config.webhooks {
+        notifiers.push(Box::new(Discord::new(
+            "webhook",
+            url,
+            discord_format(config),
+        )));
+    }
+
+    for notifier in &config.notifiers {
+        let enabled = match &config.enabled_notifiers {
+            Some(names) => names.contains(&notifier.name),
+            None => true,
+        };
+        if enabled {
+            notifiers.push(from_notifier_config(config, notifier));
+        }
+    }
+
+    notifiers
+}
+
+/// Builds the notifiers of the `[notifier.NAME]` sections in `names`, whether
+/// or not they are enabled.
+pub fn named(config: &Config, names: &[String]) -> Vec<Box<dyn Notifier>> {
+    config
+        .notifiers
+        .iter()
+        .filter(|notifier| names.contains(&notifier.name))
+        .map(|notifier| from_notifier_config(config, notifier))
+        .collect()
+}
+
+/// The format of the Discord notifiers made from `webhook` lines and the
+/// `webhook=` module argument.
+pub fn discord_format(config: &Config) -> Format {
+    Format {
+        templates: config.templates.clone(),
+        escape: Escape::Markdown,
+    }
+}
+
+fn from_notifier_config(config: &Config, notifier: &NotifierConfig) -> Box<dyn Notifier> {
+    let format = Format {
+        templates: notifier.templates.or(&config.templates),
+        escape: notifier.escape.unwrap_or(match notifier.kind {
+            NotifierKind::Discord | NotifierKind::Mattermost => Escape::Markdown,
+            NotifierKind::Slack => Escape::Slack,
+            _ => Escape::Plain,
+        }),
+    };
+
+    let inner: Box<dyn Notifier> = match notifier.kind {
+        NotifierKind::Discord => Box::new(Discord::from_config(notifier, format)),
+        NotifierKind::Slack | NotifierKind::Mattermost => Box::new(Slack::new(notifier, format)),
+        NotifierKind::Matrix => Box::new(Matrix::new(notifier, format)),
+        NotifierKind::Gotify => Box::new(Gotify::new(notifier, format)),
+        NotifierKind::Ntfy => Box::new(Ntfy::new(notifier, format)),
+        NotifierKind::Json => Box::new(Json::new(notifier, format)),
+    };
+
+    match &notifier.signing_secret {
+        Some(secret) => Box::new(Signed {
+            inner,
+            signing: Signing {
+                secret: secret.clone(),
+                header: notifier.signing_header.clone(),
+            },
+        }),
+        None => inner,
+    }
+}
+
+/// A notifier whose requests are signed, for `signing_secret`.
+struct Signed {
+    inner: Box<dyn Notifier>,
+    signing: Signing,
+}
+
+impl Notifier for Signed {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        self.inner.request(event).signed(Some(self.signing.clone()))
+    }
+}
@@ -0,0 +1,46 @@
+use super::{Format, Notifier};
+use crate::{
+    config::NotifierConfig,
+    event::Event,
+    http::{Body, Request},
+};
+
+/// Publishes messages to an ntfy topic.
+pub struct Ntfy {
+    name: String,
+    format: Format,
+    url: String,
+    token: Option<String>,
+    priority: Option<u8>,
+}
+
+impl Ntfy {
+    pub fn new(config: &NotifierConfig, format: Format) -> Ntfy {
+        Ntfy {
+            name: config.name.clone(),
+            format,
+            url: config.url.clone(),
+            token: config.token.clone(),
+            priority: config.priority,
+        }
+    }
+}
+
+impl Notifier for Ntfy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        let mut request = Request::post(&self.url, Body::Text(self.format.message(event)))
+            .header("Title", &format!("pam_rc2022 on {}", event.hostname));
+        if let Some(priority) = self.priority {
+            request = request.header("Priority", &priority.to_string());
+        }
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", &format!("Bearer {}", token));
+        }
+
+        request
+    }
+}
@@ -0,0 +1,54 @@
+use super::{Format, Notifier};
+use crate::{
+    config::NotifierConfig,
+    event::Event,
+    http::{Body, Request},
+};
+use serde_json::json;
+
+/// Posts messages to a Slack incoming webhook.
+///
+/// Mattermost incoming webhooks accept the same payload, so this is used for
+/// both.
+pub struct Slack {
+    name: String,
+    format: Format,
+    url: String,
+    channel: Option<String>,
+    username: Option<String>,
+    icon_url: Option<String>,
+}
+
+impl Slack {
+    pub fn new(config: &NotifierConfig, format: Format) -> Slack {
+        Slack {
+            name: config.name.clone(),
+            format,
+            url: config.url.clone(),
+            channel: config.channel.clone(),
+            username: config.username.clone(),
+            icon_url: config.avatar_url.clone(),
+        }
+    }
+}
+
+impl Notifier for Slack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self, event: &Event) -> Request {
+        let mut payload = json!({ "text": self.format.message(event) });
+        if let Some(channel) = &self.channel {
+            payload["channel"] = json!(channel);
+        }
+        if let Some(username) = &self.username {
+            payload["username"] = json!(username);
+        }
+        if let Some(icon_url) = &self.icon_url {
+            payload["icon_url"] = json!(icon_url);
+        }
+
+        Request::post(&self.url, Body::Json(payload))
+    }
+}
@@ -0,0 +1,123 @@
+//! Looking up users and groups in the system databases, which may well be LDAP
+//! or SSSD rather than /etc/passwd and /etc/group.
+
+use std::{
+    ffi::{CStr, CString, OsStr},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    ptr,
+};
+
+/// How big a buffer getpwnam_r and getgrnam_r get to start with.
+const INITIAL_BUF_SIZE: usize = 1024;
+/// The most groups a user is looked up in.
+const MAX_GROUPS: usize = 4096;
+
+/// Returns the IDs of every group `user` is in, primary group first, or `None`
+/// if there is no such user.
+pub fn user_groups(user: &str) -> Option<Vec<libc::gid_t>> {
+    let name = CString::new(user).ok()?;
+    let primary = with_buf(|buf| {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let r = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        (r, (!result.is_null()).then_some(pwd.pw_gid))
+    })??;
+
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let r =
+            unsafe { libc::getgrouplist(name.as_ptr(), primary, groups.as_mut_ptr(), &mut count) };
+        if r >= 0 {
+            groups.truncate(count as usize);
+            break;
+        }
+        if count as usize <= groups.len() {
+            // The list didn't fit, but we weren't told how big it needs to be.
+            groups.resize(groups.len() * 2, 0);
+        } else {
+            groups.resize(count as usize, 0);
+        }
+        if groups.len() > MAX_GROUPS {
+            return Some(vec![primary]);
+        }
+    }
+
+    groups.retain(|&gid| gid != primary);
+    groups.insert(0, primary);
+    Some(groups)
+}
+
+/// Returns the ID and home directory of `user`, or `None` if there is no such
+/// user.
+pub fn user_home(user: &str) -> Option<(libc::uid_t, PathBuf)> {
+    let name = CString::new(user).ok()?;
+    with_buf(|buf| {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let r = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        let home = (!result.is_null() && !pwd.pw_dir.is_null()).then(|| {
+            let dir = unsafe { CStr::from_ptr(pwd.pw_dir) };
+            (pwd.pw_uid, PathBuf::from(OsStr::from_bytes(dir.to_bytes())))
+        });
+        (r, home)
+    })?
+}
+
+/// Returns the ID of the group called `group`, or `None` if there is none.
+pub fn group_id(group: &str) -> Option<libc::gid_t> {
+    let name = CString::new(group).ok()?;
+    with_buf(|buf| {
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let r = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        (r, (!result.is_null()).then_some(grp.gr_gid))
+    })?
+}
+
+/// Returns true if `user` is in the group called `group`, either as their
+/// primary group or as a supplementary one.
+pub fn user_in_group(user: &str, group: &str) -> bool {
+    match (user_groups(user), group_id(group)) {
+        (Some(groups), Some(gid)) => groups.contains(&gid),
+        _ => false,
+    }
+}
+
+/// Calls `f` with a buffer for one of the `get*_r` functions, growing the
+/// buffer for as long as `f` reports `ERANGE`.
+fn with_buf<T>(mut f: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, T)) -> Option<T> {
+    let mut buf = vec![0 as libc::c_char; INITIAL_BUF_SIZE];
+    loop {
+        match f(&mut buf) {
+            (0, result) => return Some(result),
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            _ => return None,
+        }
+    }
+}
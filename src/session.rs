@@ -0,0 +1,116 @@
+//! Correlating the open and close of a session.
+//!
+//! When a session is opened, its ID and start time are stored in the pam
+//! handle with `pam_set_data`. Applications like sshd and login keep the same
+//! handle around until the session is closed, so the close callback can get
+//! them back to work out how long the session lasted.
+//!
+//! For `max_sessions`, open sessions are also [register]ed in the state
+//! directory with the process that opened them, so sessions whose process died
+//! without closing them stop counting.
+
+use crate::{
+    event::{hostname, now},
+    state, Pam,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ffi::CStr, io, path::Path};
+
+const DATA_NAME: &CStr = c"pam_rc2022_session";
+/// Where open sessions are registered, in the state directory.
+const OPEN_FILE: &str = "sessions.json";
+
+/// A session this module saw being opened.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Identifies the session in both its open and close events.
+    pub id: String,
+    /// When the session was opened, in seconds since the Unix epoch.
+    pub start: u64,
+    /// The login was held back by `[rate_limit]`, so the logout is too.
+    pub quiet: bool,
+}
+
+/// Records that a session is being opened.
+///
+/// If the session can't be stored in the pam handle, the close event will just
+/// lack a duration, so that isn't treated as an error.
+pub fn start(pamh: Pam<'_>) -> Session {
+    let start = now();
+    let session = Session {
+        id: format!("{}-{}-{}", hostname(), std::process::id(), start),
+        start,
+        quiet: false,
+    };
+    let _ = pamh.set_data(DATA_NAME, session.clone());
+    session
+}
+
+/// Marks the session [start]ed for this pam handle as [Session::quiet].
+pub fn keep_quiet(pamh: Pam<'_>, session: &Session) {
+    let session = Session {
+        quiet: true,
+        ..session.clone()
+    };
+    let _ = pamh.set_data(DATA_NAME, session);
+}
+
+/// Gets the session [start] recorded for this pam handle, if any.
+pub fn finish(pamh: Pam<'_>) -> Option<Session> {
+    // DATA_NAME is only ever stored as a Session.
+    unsafe { pamh.get_data::<Session>(DATA_NAME) }
+}
+
+/// A session in [OPEN_FILE].
+#[derive(Debug, Serialize, Deserialize)]
+struct Open {
+    id: String,
+    pid: u32,
+}
+
+/// Open sessions by user.
+type OpenSessions = BTreeMap<String, Vec<Open>>;
+
+/// Registers `session` as opened by `user` in this process.
+pub fn register(state_dir: &Path, user: &str, session: &Session) -> io::Result<()> {
+    state::update(state_dir, OPEN_FILE, |open: &mut OpenSessions| {
+        open.entry(user.to_string()).or_default().push(Open {
+            id: session.id.clone(),
+            pid: std::process::id(),
+        })
+    })
+}
+
+/// Forgets a session [register]ed by `user`.
+pub fn unregister(state_dir: &Path, user: &str, session: &Session) -> io::Result<()> {
+    state::update(state_dir, OPEN_FILE, |open: &mut OpenSessions| {
+        if let Some(sessions) = open.get_mut(user) {
+            sessions.retain(|open| open.id != session.id);
+            if sessions.is_empty() {
+                open.remove(user);
+            }
+        }
+    })
+}
+
+/// How many sessions `user` has open, forgetting those whose process is gone.
+pub fn count_open(state_dir: &Path, user: &str) -> io::Result<usize> {
+    state::update(state_dir, OPEN_FILE, |open: &mut OpenSessions| {
+        open.retain(|_, sessions| {
+            sessions.retain(|open| alive(open.pid));
+            !sessions.is_empty()
+        });
+        open.get(user).map_or(0, Vec::len)
+    })
+}
+
+/// Returns true if process `pid` exists, whether or not we may signal it.
+fn alive(pid: u32) -> bool {
+    let r = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    r == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// How long `session` has lasted so far, in seconds.
+pub fn duration(session: &Session) -> u64 {
+    now().saturating_sub(session.start)
+}
@@ -0,0 +1,248 @@
+//! A spool directory of requests that couldn't be sent, so they can be retried
+//! later instead of being lost.
+//!
+//! Every entry is a JSON file that is written to a temporary name and renamed
+//! into place, so readers never see half written entries. Entries are retried
+//! with exponential backoff by later logins and by `pam_rc2022ctl flush`.
+
+use crate::{
+    config::{HttpConfig, SpoolConfig},
+    event::now,
+    http::{self, HttpError},
+    log::{Level, Log, Message},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        fs::{DirBuilderExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A request waiting in the spool.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// The name of the notifier the request was built by.
+    pub notifier: String,
+    pub request: http::Request,
+    /// When the request was first attempted, in seconds since the Unix epoch.
+    pub created: u64,
+    /// How many times sending the request has failed.
+    pub attempts: u32,
+    /// The earliest time the request should be tried again.
+    pub next_attempt: u64,
+}
+
+/// What a [flush] did.
+#[derive(Debug, Default)]
+pub struct FlushStats {
+    /// Entries that were sent and removed.
+    pub sent: usize,
+    /// Entries that failed again and were rescheduled.
+    pub failed: usize,
+    /// Entries that weren't due yet.
+    pub waiting: usize,
+    /// Entries that were removed because they were too old or unreadable.
+    pub dropped: usize,
+}
+
+/// Adds a request that failed its first attempt to the spool.
+///
+/// If the spool is full, the oldest entries are removed to make room.
+pub fn push(config: &SpoolConfig, notifier: &str, request: http::Request) -> io::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    create_dir(&config.dir)?;
+
+    let mut names = entry_names(&config.dir)?;
+    if names.len() >= config.max_entries {
+        let excess = names.len() + 1 - config.max_entries;
+        for name in names.drain(..excess) {
+            let _ = fs::remove_file(config.dir.join(name));
+        }
+    }
+
+    let now = now();
+    let entry = Entry {
+        notifier: notifier.to_string(),
+        request,
+        created: now,
+        attempts: 1,
+        next_attempt: now + backoff(config, 1).as_secs(),
+    };
+    let name = format!("{:020}-{}-{}.json", now, std::process::id(), nanos());
+    write_entry(&config.dir, &name, &entry)
+}
+
+/// Retries every entry that is due.
+///
+/// Only one flush runs at a time. If another process is already flushing, this
+/// returns an error of kind [io::ErrorKind::WouldBlock]. What happens to
+/// individual entries is passed to `log`.
+pub fn flush(
+    config: &SpoolConfig,
+    http_config: &HttpConfig,
+    log: &mut Log,
+) -> io::Result<FlushStats> {
+    let mut stats = FlushStats::default();
+    if !config.enabled || !config.dir.exists() {
+        return Ok(stats);
+    }
+
+    let _lock = lock(&config.dir)?;
+    let now = now();
+
+    for name in entry_names(&config.dir)? {
+        let path = config.dir.join(&name);
+        let mut entry: Entry = match fs::read(&path)
+            .map_err(|why| why.to_string())
+            .and_then(|data| serde_json::from_slice(&data).map_err(|why| why.to_string()))
+        {
+            Ok(entry) => entry,
+            Err(why) => {
+                log(
+                    Level::Warning,
+                    Message::new("dropping unreadable spool entry")
+                        .field("entry", &name)
+                        .field("error", why)
+                        .into(),
+                );
+                let _ = fs::remove_file(&path);
+                stats.dropped += 1;
+                continue;
+            }
+        };
+
+        if entry.created.saturating_add(config.max_age.as_secs()) < now {
+            log(
+                Level::Warning,
+                Message::new("dropping expired spool entry")
+                    .field("entry", &name)
+                    .field("notifier", &entry.notifier)
+                    .field("attempts", entry.attempts)
+                    .into(),
+            );
+            let _ = fs::remove_file(&path);
+            stats.dropped += 1;
+            continue;
+        }
+        if entry.next_attempt > now {
+            stats.waiting += 1;
+            continue;
+        }
+
+        let started = Instant::now();
+        let result = http::send(http_config, &entry.request);
+        let message = |text: &str| {
+            Message::new(text)
+                .field("entry", &name)
+                .field("notifier", &entry.notifier)
+                .field("latency_ms", started.elapsed().as_millis())
+        };
+        match result {
+            Ok(status) => {
+                log(
+                    Level::Debug,
+                    message("sent spooled message")
+                        .field("status", status)
+                        .into(),
+                );
+                match fs::remove_file(&path) {
+                    // A concurrent push may have pruned it meanwhile.
+                    Err(why) if why.kind() != io::ErrorKind::NotFound => return Err(why),
+                    _ => {}
+                }
+                stats.sent += 1;
+            }
+            Err(why) => {
+                let mut message =
+                    message("can't send spooled message").field("attempt", entry.attempts + 1);
+                if let HttpError::Status(status) = why {
+                    message = message.field("status", status);
+                }
+                log(Level::Warning, message.field("error", &why).into());
+                entry.attempts += 1;
+                entry.next_attempt = now + backoff(config, entry.attempts).as_secs();
+                write_entry(&config.dir, &name, &entry)?;
+                stats.failed += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// How long to wait after the `attempts`th failure: `retry` doubled for every
+/// failure after the first, up to `max_retry`.
+fn backoff(config: &SpoolConfig, attempts: u32) -> Duration {
+    let factor = 1u32
+        .checked_shl(attempts.saturating_sub(1))
+        .unwrap_or(u32::MAX);
+    config
+        .retry
+        .checked_mul(factor)
+        .unwrap_or(config.max_retry)
+        .min(config.max_retry)
+}
+
+fn create_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
+
+/// Lists the names of the entries in the spool, oldest first.
+fn entry_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') && name.ends_with(".json"))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Atomically writes `entry` to `dir/name`.
+fn write_entry(dir: &Path, name: &str, entry: &Entry) -> io::Result<()> {
+    let tmp: PathBuf = dir.join(format!(".tmp-{}-{}", std::process::id(), nanos()));
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(&serde_json::to_vec(entry)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, dir.join(name))?;
+        File::open(dir)?.sync_all()
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Takes the spool lock, which is released when the returned file is dropped.
+fn lock(dir: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(dir.join(".lock"))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+fn nanos() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0)
+}
@@ -0,0 +1,65 @@
+//! Small pieces of state that have to survive between PAM transactions, such
+//! as failure counters.
+//!
+//! State is stored as JSON files in the `state_dir` from the configuration.
+//! Every update happens under an exclusive lock and is written to a temporary
+//! file that is renamed into place, so concurrent logins never lose updates
+//! or see half written files.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        fs::{DirBuilderExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+    path::Path,
+};
+
+/// Runs `f` on the state stored in `dir/name` and writes the result back.
+///
+/// Missing or unreadable state starts out as `T::default()`, since losing a
+/// counter is better than failing a login over it.
+pub fn update<T, R>(dir: &Path, name: &str, f: impl FnOnce(&mut T) -> R) -> io::Result<R>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    let _lock = lock(&dir.join(format!(".{}.lock", name)))?;
+
+    let path = dir.join(name);
+    let mut state: T = fs::read(&path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+    let result = f(&mut state);
+
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(&serde_json::to_vec(&state)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp, &path)?;
+
+    Ok(result)
+}
+
+/// Takes an exclusive lock on `path`, which is released when the returned file
+/// is dropped.
+pub(crate) fn lock(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
@@ -0,0 +1,387 @@
+//! Message templates.
+//!
+//! Templates are plain text with `{placeholder}`s that are replaced by details
+//! of the event. `{{` and `}}` stand for literal braces.
+//!
+//! | placeholder  | replaced by                                           |
+//! |--------------|-------------------------------------------------------|
+//! | `{event}`    | the kind of event, such as `login` or `auth_failure`  |
+//! | `{message}`  | the message that is sent when there is no template    |
+//! | `{user}`     | the user logging in                                   |
+//! | `{ruser}`    | the user asking to become `{user}`, for sudo and su   |
+//! | `{who}`      | `{user}`, or `{ruser} → {user}` if they differ        |
+//! | `{rhost}`    | the remote host                                       |
+//! | `{service}`  | the PAM service, such as `sshd`                       |
+//! | `{tty}`      | the terminal, such as `pts/3`                         |
+//! | `{whence}`   | ` from {rhost} via {service} on {tty}`, leaving out unknown parts |
+//! | `{hostname}` | the name of this machine                              |
+//! | `{time}`     | the local time of the event                           |
+//! | `{duration}` | how long a session lasted, a summary covers or a lockout lasts |
+//! | `{count}`    | how many events a summary stands for, or failures led to a lockout |
+//! | `{session}`  | the session ID, to match logouts to logins            |
+//! | `{id}`       | the event ID, as used in logs and approval URLs       |
+//! | `{lock}`     | what a lockout locked, such as `user:alice`           |
+//!
+//! Values are escaped for the notifier they are sent to, so a username can't
+//! inject markup or mentions. The template text itself is sent as is.
+
+use crate::event::{format_duration, Event, EventKind};
+use std::collections::BTreeMap;
+
+const PLACEHOLDERS: &[&str] = &[
+    "event", "message", "user", "ruser", "who", "rhost", "service", "tty", "whence", "hostname",
+    "time", "duration", "count", "session", "id", "lock",
+];
+
+/// How values are escaped before they are put into a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    /// Values are used as they are.
+    Plain,
+    /// Markdown as understood by Discord and Mattermost. Formatting characters
+    /// are backslash escaped and `@` mentions are broken up.
+    Markdown,
+    /// Slack's mrkdwn, where `&`, `<` and `>` have to be HTML escaped.
+    Slack,
+}
+
+impl Escape {
+    pub fn parse(value: &str) -> Result<Escape, String> {
+        Ok(match value {
+            "plain" => Escape::Plain,
+            "markdown" => Escape::Markdown,
+            "slack" => Escape::Slack,
+            _ => return Err(format!("unknown escape {:?}", value)),
+        })
+    }
+
+    /// Escapes `value` so it shows up literally.
+    pub fn apply(self, value: &str) -> String {
+        match self {
+            Escape::Plain => value.to_string(),
+            Escape::Markdown => {
+                let mut result = String::with_capacity(value.len());
+                for c in value.chars() {
+                    match c {
+                        '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']' | '(' | ')' => {
+                            result.push('\\');
+                            result.push(c);
+                        }
+                        // A zero width space stops @everyone and friends from pinging.
+                        '@' => result.push_str("@\u{200b}"),
+                        _ => result.push(c),
+                    }
+                }
+                result
+            }
+            Escape::Slack => value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+        }
+    }
+}
+
+/// A template for each kind of event, keyed by the kind's name.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    templates: BTreeMap<String, String>,
+}
+
+impl Templates {
+    /// Sets the template for the kind of event called `kind`, checking that
+    /// both exist.
+    pub fn set(&mut self, kind: &str, template: &str) -> Result<(), String> {
+        if EventKind::from_name(kind).is_none() {
+            return Err(format!("unknown event {:?}", kind));
+        }
+        check(template)?;
+        self.templates
+            .insert(kind.to_string(), template.to_string());
+        Ok(())
+    }
+
+    /// Returns these templates, with any missing ones taken from `fallback`.
+    pub fn or(&self, fallback: &Templates) -> Templates {
+        let mut templates = fallback.templates.clone();
+        templates.extend(self.templates.clone());
+        Templates { templates }
+    }
+
+    /// Renders the message for `event`, using its default text if there is no
+    /// template for its kind.
+    pub fn render(&self, event: &Event, escape: Escape) -> String {
+        match self.templates.get(event.kind.name()) {
+            Some(template) => render(template, event, escape),
+            None => escape.apply(&event.to_string()),
+        }
+    }
+}
+
+/// Checks that every placeholder in `template` exists and every brace is
+/// matched.
+fn check(template: &str) -> Result<(), String> {
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed {{ in {:?}", template))?;
+                if !PLACEHOLDERS.contains(&&rest[..end]) {
+                    return Err(format!("unknown placeholder {{{}}}", &rest[..end]));
+                }
+                chars = rest[end + 1..].chars();
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+            }
+            '}' => return Err(format!("unmatched }} in {:?}", template)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Renders `template` for `event`. Templates are [check]ed when the
+/// configuration is loaded, so anything unexpected is kept as it is.
+pub fn render(template: &str, event: &Event, escape: Escape) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(idx) = rest.find(['{', '}']) {
+        result.push_str(&rest[..idx]);
+        let tail = &rest[idx..];
+
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            result.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+
+        match tail[1..].find('}').map(|end| &tail[1..end + 1]) {
+            Some(name) if tail.starts_with('{') && PLACEHOLDERS.contains(&name) => {
+                result.push_str(&escape.apply(&value(name, event)));
+                rest = &tail[name.len() + 2..];
+            }
+            _ => {
+                result.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn value(name: &str, event: &Event) -> String {
+    match name {
+        "event" => event.kind.name().to_string(),
+        "message" => event.to_string(),
+        "user" => event.user.clone(),
+        "ruser" => event.ruser.clone(),
+        "who" => event.who(),
+        "rhost" => event.rhost.clone(),
+        "service" => event.service.clone(),
+        "tty" => event.tty.clone(),
+        "whence" => event.whence(),
+        "hostname" => event.hostname.clone(),
+        "time" => local_time(event.time),
+        "duration" => event.duration.map(format_duration).unwrap_or_default(),
+        "count" => event.count.map(|c| c.to_string()).unwrap_or_default(),
+        "session" => event.session.clone().unwrap_or_default(),
+        "id" => event.id.clone(),
+        "lock" => event.lock.clone().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+/// Formats seconds since the Unix epoch as local time, like
+/// `2022-07-06 13:37:00 EDT`.
+pub fn local_time(time: u64) -> String {
+    strftime(time, true, c"%Y-%m-%d %H:%M:%S %Z")
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp, like
+/// `2022-07-06T17:37:00Z`.
+pub fn utc_time(time: u64) -> String {
+    strftime(time, false, c"%Y-%m-%dT%H:%M:%SZ")
+}
+
+fn strftime(time: u64, local: bool, format: &std::ffi::CStr) -> String {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let converted = unsafe {
+        if local {
+            libc::localtime_r(&time, &mut tm)
+        } else {
+            libc::gmtime_r(&time, &mut tm)
+        }
+    };
+    if converted.is_null() {
+        return time.to_string();
+    }
+
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> Event {
+        Event {
+            id: "1657129020-4711-1".to_string(),
+            kind: EventKind::Logout,
+            user: "root".to_string(),
+            rhost: "192.0.2.1".to_string(),
+            ruser: "bob".to_string(),
+            service: "sshd".to_string(),
+            tty: "pts/3".to_string(),
+            hostname: "example".to_string(),
+            time: 1657129020,
+            session: Some("s1".to_string()),
+            duration: Some(3725),
+            count: None,
+            lock: None,
+        }
+    }
+
+    #[test]
+    fn checks_placeholders_and_braces() {
+        for template in [
+            "",
+            "no placeholders",
+            "{user} on {hostname}",
+            "{{user}}",
+            "{{{user}}}",
+            "}}{{",
+        ] {
+            assert_eq!(check(template), Ok(()), "{:?}", template);
+        }
+        assert_eq!(check("{nope}"), Err("unknown placeholder {nope}".into()));
+        assert_eq!(check("{}"), Err("unknown placeholder {}".into()));
+        assert_eq!(
+            check("{ user }"),
+            Err("unknown placeholder { user }".into())
+        );
+        assert_eq!(check("{user"), Err("unclosed { in \"{user\"".into()));
+        assert_eq!(check("user}"), Err("unmatched } in \"user}\"".into()));
+        assert_eq!(check("{{user}"), Err("unmatched } in \"{{user}\"".into()));
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let event = event();
+        assert_eq!(
+            render(
+                "{event}: {who}{whence} after {duration}",
+                &event,
+                Escape::Plain
+            ),
+            "logout: bob → root from 192.0.2.1 via sshd on pts/3 after 1h 2m 5s"
+        );
+        assert_eq!(
+            render("{id} {session} [{count}] [{lock}]", &event, Escape::Plain),
+            "1657129020-4711-1 s1 [] []"
+        );
+        assert_eq!(
+            render("{message}", &event, Escape::Plain),
+            event.to_string()
+        );
+    }
+
+    #[test]
+    fn renders_doubled_braces_as_literal_ones() {
+        let event = event();
+        assert_eq!(render("{{user}}", &event, Escape::Plain), "{user}");
+        assert_eq!(render("{{{user}}}", &event, Escape::Plain), "{root}");
+        assert_eq!(render("}}{{", &event, Escape::Plain), "}{");
+    }
+
+    #[test]
+    fn keeps_what_check_would_reject() {
+        let event = event();
+        for template in ["{nope} {", "}{", "{user", "{}"] {
+            assert_eq!(render(template, &event, Escape::Plain), template);
+        }
+        assert_eq!(render("}{user}", &event, Escape::Plain), "}root");
+    }
+
+    #[test]
+    fn escapes_values_but_not_the_template() {
+        let mut event = event();
+        event.user = "*x*".to_string();
+        event.ruser = String::new();
+        assert_eq!(
+            render("**{user}**", &event, Escape::Markdown),
+            "**\\*x\\***"
+        );
+    }
+
+    #[test]
+    fn escapes_markdown() {
+        assert_eq!(
+            Escape::Markdown.apply("a_b*c~d`e|f>g#h[i](j)\\"),
+            "a\\_b\\*c\\~d\\`e\\|f\\>g\\#h\\[i\\]\\(j\\)\\\\"
+        );
+        assert_eq!(Escape::Markdown.apply("@everyone"), "@\u{200b}everyone");
+        assert_eq!(Escape::Markdown.apply("plain text"), "plain text");
+    }
+
+    #[test]
+    fn escapes_html_for_slack() {
+        assert_eq!(
+            Escape::Slack.apply("<!channel> & <@U123>"),
+            "&lt;!channel&gt; &amp; &lt;@U123&gt;"
+        );
+        // Ampersands are escaped first, so entities aren't escaped twice.
+        assert_eq!(Escape::Slack.apply("&lt;"), "&amp;lt;");
+        assert_eq!(Escape::Plain.apply("<*@x*>"), "<*@x*>");
+    }
+
+    #[test]
+    fn parses_escapes() {
+        assert_eq!(Escape::parse("plain"), Ok(Escape::Plain));
+        assert_eq!(Escape::parse("markdown"), Ok(Escape::Markdown));
+        assert_eq!(Escape::parse("slack"), Ok(Escape::Slack));
+        assert!(Escape::parse("html").is_err());
+    }
+
+    #[test]
+    fn falls_back_to_the_default_message() {
+        let mut templates = Templates::default();
+        templates.set("login", "{user} is in").unwrap();
+        assert!(templates.set("logon", "{user}").is_err());
+        assert!(templates.set("logout", "{usr}").is_err());
+
+        let mut event = event();
+        event.user = "_x_".to_string();
+        assert_eq!(
+            templates.render(&event, Escape::Markdown),
+            Escape::Markdown.apply(&event.to_string())
+        );
+        event.kind = EventKind::Login;
+        assert_eq!(templates.render(&event, Escape::Markdown), "\\_x\\_ is in");
+    }
+
+    #[test]
+    fn formats_utc_times() {
+        assert_eq!(utc_time(1657129020), "2022-07-06T17:37:00Z");
+        assert_eq!(utc_time(0), "1970-01-01T00:00:00Z");
+    }
+}
@@ -0,0 +1,403 @@
+//! RFC 6238 time-based one-time passwords, asked for as a second factor with
+//! the `totp` module argument.
+//!
+//! Every user has a secret file, either `secrets_dir/USER`, owned by root, or
+//! `~/.config/pam_rc2022/totp`, owned by the user. Neither may be accessible
+//! by anyone else. The first line is the base32 secret and every other line
+//! is a scratch code, which can be used once instead of a TOTP code when the
+//! user's phone is out of reach:
+//!
+//! ```text
+//! JBSWY3DPEHPK3PXP
+//! 31415926
+//! 27182818
+//! ```
+//!
+//! Lines starting with `"` are ignored, so files made by google-authenticator
+//! work as they are.
+//!
+//! Codes are only accepted once: the last time step a user logged in with and
+//! the scratch codes they used are kept in the state directory.
+
+use crate::{
+    args::ModuleArgs,
+    config::{Config, TotpConfig},
+    crypto::{constant_time_eq, hex, hmac_sha1, sha256, unbase32},
+    diagnose,
+    event::now,
+    log::{Logger, Message},
+    passwd, state, Pam, PamResult, PamResultCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::Path,
+};
+use zeroize::Zeroizing;
+
+/// A user's secret and scratch codes.
+pub struct Secret {
+    key: Zeroizing<Vec<u8>>,
+    scratch_codes: Vec<Zeroizing<String>>,
+}
+
+/// How a code was accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    /// It was the TOTP code for now, give or take `skew`.
+    Code,
+    /// It was a scratch code. This many are left.
+    Scratch { left: usize },
+}
+
+/// What was used already, by user.
+#[derive(Default, Serialize, Deserialize)]
+struct Used {
+    /// The time step of the last TOTP code accepted.
+    step: u64,
+    /// The SHA-256 of each scratch code used.
+    scratch: Vec<String>,
+}
+
+/// Reads the secret file of `user`, or returns `None` if they don't have one.
+pub fn load(config: &TotpConfig, user: &str) -> io::Result<Option<Secret>> {
+    if let Some(dir) = &config.secrets_dir {
+        if user.contains('/') || user.starts_with('.') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid user name {:?}", user),
+            ));
+        }
+        if let Some(secret) = read(&dir.join(user), 0)? {
+            return Ok(Some(secret));
+        }
+    }
+
+    match (&config.user_file, passwd::user_home(user)) {
+        (Some(file), Some((uid, home))) => read(&home.join(file), uid),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the secret file at `path`, which has to be owned by `owner` or root
+/// and not be accessible by anyone else.
+fn read(path: &Path, owner: libc::uid_t) -> io::Result<Option<Secret>> {
+    let mut file = match OpenOptions::new()
+        .read(true)
+        // Without O_NONBLOCK, opening a FIFO would wait for a writer before
+        // the permissions are checked.
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(why) => return Err(why),
+    };
+    check_permissions(&file, path, owner)?;
+
+    let mut contents = Zeroizing::new(String::new());
+    file.read_to_string(&mut contents)?;
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('"'));
+
+    let key = lines
+        .next()
+        .and_then(unbase32)
+        .filter(|key| !key.is_empty())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} doesn't start with a base32 secret", path.display()),
+            )
+        })?;
+
+    Ok(Some(Secret {
+        key: Zeroizing::new(key),
+        scratch_codes: lines.map(|line| Zeroizing::new(line.to_string())).collect(),
+    }))
+}
+
+fn check_permissions(file: &File, path: &Path, owner: libc::uid_t) -> io::Result<()> {
+    let meta = file.metadata()?;
+    let why = if !meta.is_file() {
+        "is not a regular file"
+    } else if meta.uid() != owner && meta.uid() != 0 {
+        "is owned by someone else"
+    } else if meta.mode() & 0o077 != 0 {
+        "is accessible by others"
+    } else {
+        return Ok(());
+    };
+
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} {}", path.display(), why),
+    ))
+}
+
+/// The HOTP code for time step `step`, RFC 4226.
+pub fn code(key: &[u8], step: u64, digits: u32) -> String {
+    let mac = hmac_sha1(key, &step.to_be_bytes());
+    let offset = (mac[mac.len() - 1] & 0xf) as usize;
+    let truncated = u32::from_be_bytes([
+        mac[offset] & 0x7f,
+        mac[offset + 1],
+        mac[offset + 2],
+        mac[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// Checks `answer` against the TOTP codes of `secret` around now and its
+/// scratch codes, and records it as used if it is accepted.
+///
+/// Returns `None` if the code is wrong or was used before.
+pub fn verify(
+    config: &Config,
+    user: &str,
+    secret: &Secret,
+    answer: &str,
+) -> io::Result<Option<Verified>> {
+    let totp = &config.totp;
+    let answer: Zeroizing<String> = Zeroizing::new(answer.split_whitespace().collect());
+    let current = now() / totp.period.as_secs();
+
+    state::update(
+        &config.state_dir,
+        "totp.json",
+        |used: &mut BTreeMap<String, Used>| {
+            let used = used.entry(user.to_string()).or_default();
+
+            let first = current.saturating_sub(totp.skew.into()).max(used.step + 1);
+            let matched = (first..=current + u64::from(totp.skew)).find(|&step| {
+                constant_time_eq(
+                    code(&secret.key, step, totp.digits).as_bytes(),
+                    answer.as_bytes(),
+                )
+            });
+            if let Some(step) = matched {
+                used.step = step;
+                return Some(Verified::Code);
+            }
+
+            let hashes: Vec<String> = secret
+                .scratch_codes
+                .iter()
+                .map(|code| hex(&sha256(code.as_bytes())))
+                .collect();
+            // Scratch codes are compared by hash, which doesn't leak how much
+            // of a code was right.
+            let hash = hex(&sha256(answer.as_bytes()));
+            if !hashes.contains(&hash) || used.scratch.contains(&hash) {
+                return None;
+            }
+            // Forget codes that were taken out of the file.
+            used.scratch.retain(|used| hashes.contains(used));
+            used.scratch.push(hash);
+            let left = hashes
+                .iter()
+                .filter(|hash| !used.scratch.contains(hash))
+                .count();
+            Some(Verified::Scratch { left })
+        },
+    )
+}
+
+/// Asks the user logging in for a TOTP code and checks it.
+///
+/// Fails with `PAM_AUTH_ERR` for a wrong code, and with
+/// `PAM_AUTHINFO_UNAVAIL` if the user's secret can't be read. Users without
+/// a secret get `PAM_IGNORE` with `nullok` and `PAM_AUTH_ERR` otherwise.
+pub fn authenticate(pamh: Pam<'_>, args: &ModuleArgs, config: &Config) -> PamResult<()> {
+    let logger = Logger::new(pamh, args.debug);
+    let user = pamh.get_user()?;
+
+    let secret = match load(&config.totp, &user) {
+        Ok(Some(secret)) => secret,
+        Ok(None) if config.totp.nullok => {
+            logger.debug(Message::new("no TOTP secret, letting user through").field("user", &user));
+            return Err(PamResultCode::PAM_IGNORE);
+        }
+        Ok(None) => {
+            logger.warning(Message::new("no TOTP secret").field("user", &user));
+            return Err(PamResultCode::PAM_AUTH_ERR);
+        }
+        Err(why) => {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't read TOTP secret of {}: {}", user, why),
+            );
+            return Err(PamResultCode::PAM_AUTHINFO_UNAVAIL);
+        }
+    };
+
+    let answer = pamh.prompt_echo_off(&config.totp.prompt)?;
+    match verify(config, &user, &secret, &answer) {
+        Ok(Some(Verified::Code)) => Ok(()),
+        Ok(Some(Verified::Scratch { left })) => {
+            logger.info(
+                Message::new("used TOTP scratch code")
+                    .field("user", &user)
+                    .field("left", left),
+            );
+            Ok(())
+        }
+        Ok(None) => {
+            logger.warning(Message::new("wrong TOTP code").field("user", &user));
+            Err(PamResultCode::PAM_AUTH_ERR)
+        }
+        Err(why) => {
+            diagnose(
+                pamh,
+                args,
+                config.diagnostics,
+                format!("can't update TOTP state: {}", why),
+            );
+            Err(PamResultCode::PAM_AUTHINFO_UNAVAIL)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 secret of RFC 6238 Appendix B.
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    fn config(name: &str) -> Config {
+        let state_dir =
+            std::env::temp_dir().join(format!("pam_rc2022-totp-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&state_dir);
+        let mut config = Config::default();
+        config.state_dir = state_dir;
+        config
+    }
+
+    fn secret(scratch_codes: &[&str]) -> Secret {
+        Secret {
+            key: Zeroizing::new(RFC_KEY.to_vec()),
+            scratch_codes: scratch_codes
+                .iter()
+                .map(|code| Zeroizing::new(code.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn rfc_6238_vectors() {
+        for (time, expected) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1234567890, "89005924"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(code(RFC_KEY, time / 30, 8), expected, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn six_digits_are_the_last_six() {
+        assert_eq!(code(RFC_KEY, 59 / 30, 6), "287082");
+    }
+
+    #[test]
+    fn rejects_replayed_time_step() {
+        let config = config("replay");
+        let secret = secret(&[]);
+        let current = code(RFC_KEY, now() / 30, 6);
+
+        assert_eq!(
+            verify(&config, "alice", &secret, &current).unwrap(),
+            Some(Verified::Code)
+        );
+        assert_eq!(verify(&config, "alice", &secret, &current).unwrap(), None);
+        // Other users have their own steps.
+        assert_eq!(
+            verify(&config, "bob", &secret, &current).unwrap(),
+            Some(Verified::Code)
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_code() {
+        let config = config("wrong");
+        let secret = secret(&[]);
+        let wrong = code(RFC_KEY, now() / 30 + 10, 6);
+
+        assert_eq!(verify(&config, "alice", &secret, &wrong).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_reused_scratch_code() {
+        let config = config("scratch");
+        let secret = secret(&["31415926", "27182818"]);
+
+        assert_eq!(
+            verify(&config, "alice", &secret, "3141 5926").unwrap(),
+            Some(Verified::Scratch { left: 1 })
+        );
+        assert_eq!(verify(&config, "alice", &secret, "31415926").unwrap(), None);
+        assert_eq!(
+            verify(&config, "alice", &secret, "27182818").unwrap(),
+            Some(Verified::Scratch { left: 0 })
+        );
+    }
+
+    fn secret_file(name: &str, contents: &str, mode: u32) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = config(name).state_dir;
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secret");
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_secret_files() {
+        let path = secret_file(
+            "read",
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n\" TOTP_AUTH\n\n31415926\n",
+            0o600,
+        );
+        let secret = read(&path, unsafe { libc::getuid() }).unwrap().unwrap();
+        assert_eq!(&secret.key[..], RFC_KEY);
+        assert_eq!(secret.scratch_codes.len(), 1);
+        assert_eq!(secret.scratch_codes[0].as_str(), "31415926");
+
+        assert!(read(&path.with_file_name("missing"), 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_secret_files_others_can_read() {
+        let path = secret_file("mode", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n", 0o644);
+        let why = read(&path, unsafe { libc::getuid() }).err().unwrap();
+        assert_eq!(why.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn refuses_fifos_without_waiting_for_a_writer() {
+        let dir = config("fifo").state_dir;
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secret");
+        let c_path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let why = read(&path, unsafe { libc::getuid() }).err().unwrap();
+        assert_eq!(why.kind(), io::ErrorKind::PermissionDenied);
+        assert!(why.to_string().ends_with("is not a regular file"));
+    }
+}